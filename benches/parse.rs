@@ -0,0 +1,78 @@
+//! Benchmarks parsing a large file with many AutoValue classes. Run with `cargo bench`.
+//!
+//! `parse_source` compiles each of its tree-sitter queries once per process. For comparison,
+//! `parse_source_compiling_queries_per_call` adds the cost of the previous approach, which
+//! compiled every query on each call, along with a method query for each class (with the
+//! class name spliced into it).
+
+use tree_sitter::Query;
+
+const CLASSES: usize = 500;
+const METHODS: usize = 20;
+
+fn main() {
+    divan::main();
+}
+
+/// A file with `CLASSES` nested AutoValue classes, each with `METHODS` properties.
+fn large_file() -> String {
+    let mut source_code = String::from("package com.example;\nimport com.google.auto.value.AutoValue;\n");
+    source_code.push_str("class Outer {\n");
+    for class in 0..CLASSES {
+        source_code.push_str(&format!("  @AutoValue\n  abstract static class Value{class} {{\n"));
+        for method in 0..METHODS {
+            source_code.push_str(&format!("    abstract java.util.List<String> field{method}();\n"));
+        }
+        source_code.push_str("  }\n");
+    }
+    source_code.push_str("}\n");
+    source_code
+}
+
+/// Compiles the queries that were compiled on every call before they were shared.
+fn compile_queries_per_call(class_count: usize) {
+    let compile = |source: &str| Query::new(&tree_sitter_java::language(), source).unwrap();
+    compile("(package_declaration [(identifier) (scoped_identifier)] @package-name)");
+    compile("(import_declaration) @import");
+    compile("(class_declaration (modifiers) @modifiers name: (identifier) @class-name)");
+    compile(r#"
+      [
+        (class_declaration name: (identifier) @type-name)
+        (interface_declaration name: (identifier) @type-name)
+        (enum_declaration name: (identifier) @type-name)
+        (record_declaration name: (identifier) @type-name)
+        (annotation_type_declaration name: (identifier) @type-name)
+      ]
+    "#);
+    for class in 0..class_count {
+        compile(&format!(r#"
+          (class_declaration
+            name: (identifier) @class-name (#eq? @class-name "Value{class}")
+            body: (class_body
+              (method_declaration
+                (modifiers) @modifiers
+                type: _ @return-type
+                name: (identifier) @method-name
+                parameters: (formal_parameters))))
+        "#));
+    }
+}
+
+#[divan::bench(sample_count = 10)]
+fn parse_source(bencher: divan::Bencher) {
+    let source_code = large_file();
+    let result = mavir::parse_source("Large.java", &source_code).unwrap();
+    assert_eq!(result.class_declarations.len(), CLASSES);
+    assert!(result.class_declarations.iter().all(|c| c.methods.len() == METHODS));
+
+    bencher.bench(|| mavir::parse_source("Large.java", &source_code).unwrap());
+}
+
+#[divan::bench(sample_count = 10)]
+fn parse_source_compiling_queries_per_call(bencher: divan::Bencher) {
+    let source_code = large_file();
+    bencher.bench(|| {
+        compile_queries_per_call(CLASSES);
+        mavir::parse_source("Large.java", &source_code).unwrap()
+    });
+}
@@ -0,0 +1,20 @@
+use std::env;
+use std::path::Path;
+
+/// Generates the C header for the functions in `src/ffi.rs` into `OUT_DIR`. Only that file is
+/// parsed, so nothing else in the crate leaks into the C API. The committed copy in `include/`
+/// is checked against this one by the `ffi` tests.
+fn main() {
+    println!("cargo:rerun-if-changed=src/ffi.rs");
+    println!("cargo:rerun-if-changed=cbindgen.toml");
+
+    let crate_dir = env::var("CARGO_MANIFEST_DIR").unwrap();
+    let out_dir = env::var("OUT_DIR").unwrap();
+    let config = cbindgen::Config::from_root_or_default(&crate_dir);
+    cbindgen::Builder::new()
+        .with_src(Path::new(&crate_dir).join("src/ffi.rs"))
+        .with_config(config)
+        .generate()
+        .expect("Could not generate the C header")
+        .write_to_file(Path::new(&out_dir).join("mavir.h"));
+}
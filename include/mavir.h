@@ -0,0 +1,78 @@
+#ifndef MAVIR_H
+#define MAVIR_H
+
+/* Generated by cbindgen from src/ffi.rs. Do not edit. */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+// The severity of a diagnostic.
+typedef enum MavirSeverity {
+  // The code was generated, but doesn't support a feature used by the source.
+  MavirWarning = 1,
+  // No code could be generated for the source.
+  MavirError = 2,
+} MavirSeverity;
+
+// A generated source file, with a path relative to the root of the generated sources (such
+// as `com/example/AutoValue_Foo.java`).
+typedef struct MavirGeneratedFile {
+  char *path;
+  char *contents;
+} MavirGeneratedFile;
+
+// A problem found in the source. Lines and columns are 1-based, and columns count bytes.
+// Diagnostics that don't refer to a position in the source have a line of 0.
+typedef struct MavirDiagnostic {
+  enum MavirSeverity severity;
+  size_t start_line;
+  size_t start_column;
+  size_t end_line;
+  size_t end_column;
+  char *message;
+} MavirDiagnostic;
+
+// The files generated for a source, and the diagnostics found in it.
+typedef struct MavirResult {
+  struct MavirGeneratedFile *files;
+  size_t files_len;
+  struct MavirDiagnostic *diagnostics;
+  size_t diagnostics_len;
+} MavirResult;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+// Returns the version of mavir. The string is static and must not be freed.
+const char *mavir_version(void);
+
+// Generates the code for the AutoValue classes in a Java source file. `path_hint` identifies
+// the source in diagnostics and need not exist; it may be NULL. `source` points to
+// `source_len` bytes of UTF-8, which need not be NUL-terminated.
+//
+// Always returns a result, which must be freed with `mavir_result_free`. Sources with syntax
+// errors produce an error diagnostic rather than code generated from a partial parse.
+//
+// # Safety
+//
+// `path_hint` must be NULL or a NUL-terminated string, and `source` must be valid for reads
+// of `source_len` bytes. Both are only read during the call.
+struct MavirResult *mavir_generate(const char *path_hint, const uint8_t *source, size_t source_len);
+
+// Frees a result returned by `mavir_generate`, along with all of its strings. Does nothing
+// for NULL.
+//
+// # Safety
+//
+// `result` must be NULL or a result returned by `mavir_generate` that hasn't been freed yet.
+void mavir_result_free(struct MavirResult *result);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* MAVIR_H */
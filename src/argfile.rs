@@ -0,0 +1,208 @@
+use std::ffi::OsString;
+use std::fs;
+use std::path::PathBuf;
+
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum ArgfileError {
+    #[error("Could not read argument file {0}: {1}")]
+    CannotReadFile(String, String),
+    #[error("Argument file includes itself: {0}")]
+    RecursiveArgfile(String),
+}
+
+pub type Result<T> = std::result::Result<T, ArgfileError>;
+
+/// Expands `@path` arguments into the arguments contained in the file at `path`, as javac does.
+/// Argument files may themselves contain `@path` arguments. A leading `@@` escapes the
+/// expansion and is passed through as a literal argument starting with `@`. The first
+/// argument (the program name) is never expanded.
+pub fn expand_args<I: IntoIterator<Item = OsString>>(args: I) -> Result<Vec<OsString>> {
+    expand_args_with_files(args).map(|(expanded, _)| expanded)
+}
+
+/// Expands the arguments as `expand_args` does, also returning the paths of the argument
+/// files that were read (e.g. to list them as dependencies in a depfile).
+pub fn expand_args_with_files<I: IntoIterator<Item = OsString>>(args: I) -> Result<(Vec<OsString>, Vec<String>)> {
+    let mut args = args.into_iter();
+    let mut expanded: Vec<OsString> = args.next().into_iter().collect();
+    let mut open_files = vec![];
+    let mut read_files = vec![];
+    for arg in args {
+        expand_arg(arg, &mut expanded, &mut open_files, &mut read_files)?;
+    }
+    Ok((expanded, read_files))
+}
+
+fn expand_arg(
+    arg: OsString,
+    expanded: &mut Vec<OsString>,
+    open_files: &mut Vec<PathBuf>,
+    read_files: &mut Vec<String>,
+) -> Result<()> {
+    let Some(path) = arg.to_str().and_then(|a| a.strip_prefix('@')) else {
+        expanded.push(arg);
+        return Ok(());
+    };
+    if path.starts_with('@') {
+        expanded.push(OsString::from(path));
+        return Ok(());
+    }
+
+    let path_buf = PathBuf::from(path);
+    if open_files.contains(&path_buf) {
+        return Err(ArgfileError::RecursiveArgfile(path.to_string()));
+    }
+    let contents = fs::read_to_string(&path_buf)
+        .map_err(|e| ArgfileError::CannotReadFile(path.to_string(), e.to_string()))?;
+
+    read_files.push(path.to_string());
+    open_files.push(path_buf);
+    for token in tokenize(&contents) {
+        expand_arg(OsString::from(token), expanded, open_files, read_files)?;
+    }
+    open_files.pop();
+    Ok(())
+}
+
+/// Splits the contents of an argument file into arguments using javac's rules: arguments are
+/// separated by whitespace, and may be quoted (in whole or part) with single or double quotes.
+/// A line break always ends an argument, even within quotes. Within quotes, `\n`, `\r`, `\t`
+/// and `\f` are escapes, a backslash followed by a line break continues the argument on the
+/// next line (skipping leading whitespace), and any other escaped character is kept as is.
+/// A `#` at the start of an argument comments out the rest of the line.
+pub fn tokenize(contents: &str) -> Vec<String> {
+    let mut tokens = vec![];
+    let mut chars = contents.chars().peekable();
+    loop {
+        // Skip whitespace and comments between arguments
+        while let Some(&c) = chars.peek() {
+            if c.is_whitespace() {
+                chars.next();
+            } else if c == '#' {
+                while chars.next_if(|&c| c != '\n' && c != '\r').is_some() {}
+            } else {
+                break;
+            }
+        }
+        if chars.peek().is_none() {
+            return tokens;
+        }
+
+        let mut token = String::new();
+        let mut quote = None;
+        while let Some(c) = chars.next() {
+            match c {
+                ' ' | '\t' | '\x0c' if quote.is_none() => break,
+                '\n' | '\r' => break,
+                '\'' | '"' if quote.is_none() => quote = Some(c),
+                '\'' | '"' if quote == Some(c) => quote = None,
+                '\\' if quote.is_some() => match chars.next() {
+                    Some('\n') | Some('\r') => {
+                        while chars.next_if(|&c| matches!(c, ' ' | '\t' | '\n' | '\r' | '\x0c')).is_some() {}
+                    }
+                    Some('n') => token.push('\n'),
+                    Some('r') => token.push('\r'),
+                    Some('t') => token.push('\t'),
+                    Some('f') => token.push('\x0c'),
+                    Some(escaped) => token.push(escaped),
+                    None => {}
+                },
+                _ => token.push(c),
+            }
+        }
+        tokens.push(token);
+    }
+}
+
+/// Quotes an argument for an argument file, so that `tokenize` reads it back unchanged.
+/// Arguments without special characters are left as is.
+pub fn quote(arg: &str) -> String {
+    let needs_quotes = arg.is_empty()
+        || arg.starts_with('#')
+        || arg.contains(|c: char| c.is_whitespace() || c == '\'' || c == '"' || c == '\\');
+    if !needs_quotes {
+        return arg.to_string();
+    }
+
+    let mut quoted = String::from("\"");
+    for c in arg.chars() {
+        match c {
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            '\x0c' => quoted.push_str("\\f"),
+            '"' | '\\' => {
+                quoted.push('\\');
+                quoted.push(c);
+            }
+            _ => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+
+#[cfg(test)]
+mod test {
+    use std::ffi::OsString;
+    use std::fs;
+    use tempdir::TempDir;
+
+    fn os_args(args: &[&str]) -> Vec<OsString> {
+        args.iter().map(OsString::from).collect()
+    }
+
+    #[test]
+    fn tokenize_whitespace_and_newlines() {
+        assert_eq!(super::tokenize("-f  A.java\n-f\tB.java\r\n\n-o out.srcjar\n"),
+                   vec!["-f", "A.java", "-f", "B.java", "-o", "out.srcjar"]);
+    }
+
+    #[test]
+    fn tokenize_quotes_escapes_and_comments() {
+        assert_eq!(super::tokenize(r#"'a b' "c d" pre"fix"ed 'it\'s' "tab\tbed""#),
+                   vec!["a b", "c d", "prefixed", "it's", "tab\tbed"]);
+        assert_eq!(super::tokenize("# comment\n-f A.java # trailing\n'long \\\n    name'"),
+                   vec!["-f", "A.java", "long name"]);
+        assert_eq!(super::tokenize(r"C:\path\File.java"), vec![r"C:\path\File.java"]);
+        assert_eq!(super::tokenize("'unterminated\nnext"), vec!["unterminated", "next"]);
+    }
+
+    #[test]
+    fn quoted_arguments_tokenize_unchanged() {
+        let args = ["plain/File.java", "with space.java", r#"quo"te's"#, r"C:\dir\F.java", "#hash", "tab\tnl\n"];
+        let contents = args.iter().map(|a| super::quote(a)).collect::<Vec<_>>().join("\n");
+        assert_eq!(super::tokenize(&contents), args);
+        assert_eq!(super::quote("plain/File.java"), "plain/File.java");
+    }
+
+    #[test]
+    fn expands_nested_argfiles() {
+        let dir = TempDir::new("mavir-argfile").unwrap();
+        let inner = dir.path().join("inner.params");
+        let outer = dir.path().join("outer.params");
+        fs::write(&inner, "-f 'B C.java'\n").unwrap();
+        fs::write(&outer, format!("-f A.java\n@{}\n", inner.display())).unwrap();
+
+        let (expanded, read_files) = super::expand_args_with_files(os_args(&[
+            "mavir", &format!("@{}", outer.display()), "@@literal", "-o", "out.srcjar",
+        ])).unwrap();
+        assert_eq!(expanded, os_args(&[
+            "mavir", "-f", "A.java", "-f", "B C.java", "@literal", "-o", "out.srcjar",
+        ]));
+        assert_eq!(read_files, vec![outer.display().to_string(), inner.display().to_string()]);
+    }
+
+    #[test]
+    fn recursive_argfile_is_an_error() {
+        let dir = TempDir::new("mavir-argfile").unwrap();
+        let params = dir.path().join("self.params");
+        fs::write(&params, format!("@{}", params.display())).unwrap();
+
+        let result = super::expand_args(os_args(&["mavir", &format!("@{}", params.display())]));
+        assert!(matches!(result, Err(super::ArgfileError::RecursiveArgfile(_))));
+    }
+}
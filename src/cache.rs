@@ -0,0 +1,267 @@
+use std::collections::HashSet;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, SystemTime};
+
+use log::debug;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use mavir::generate::GeneratedFile;
+use mavir::parse::ParseResult;
+
+#[derive(Debug, Error)]
+pub enum CacheError {
+    #[error("Could not access cache directory {0}: {1}")]
+    CannotAccessCache(String, String),
+    #[error("Could not write cache entry {0}: {1}")]
+    CannotWriteEntry(String, String),
+}
+
+pub type Result<T> = std::result::Result<T, CacheError>;
+
+/// Prefix of every file mavir writes to the cache directory. Only files with this prefix
+/// are ever removed from it.
+const FILE_PREFIX: &str = "mavir-";
+
+/// Temporary files older than this are left over from a run that was killed mid-write.
+const STALE_TEMP_FILE_AGE: Duration = Duration::from_secs(60 * 60);
+
+/// The results of processing a single input file.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CacheEntry {
+    pub parse_result: ParseResult,
+    pub generated_files: Vec<GeneratedFile>,
+}
+
+/// An on-disk cache of parse results and generated sources, keyed by a hash of each input
+/// file's contents and the mavir version. Entries that aren't used during a run are evicted
+/// at the end of it, so a cache directory should only be used for a single set of inputs.
+#[derive(Debug)]
+pub struct Cache {
+    dir: PathBuf,
+    used_keys: Mutex<HashSet<String>>,
+    /// Numbers temporary files, which must be unique between threads as well as processes
+    next_temp_file: AtomicUsize,
+}
+
+impl Cache {
+    pub fn open(dir: &str) -> Result<Cache> {
+        fs::create_dir_all(dir)
+            .map_err(|e| CacheError::CannotAccessCache(dir.to_string(), e.to_string()))?;
+        Ok(Cache {
+            dir: PathBuf::from(dir),
+            used_keys: Mutex::new(HashSet::new()),
+            next_temp_file: AtomicUsize::new(0),
+        })
+    }
+
+    /// The cache key for a file whose contents have the given hash (from
+    /// `manifest::content_hash`). The key includes the mavir version, since the cached results
+    /// depend on it.
+    pub fn key(content_hash: &str) -> String {
+        format!("{}-{}", content_hash, env!("CARGO_PKG_VERSION"))
+    }
+
+    /// Looks up the entry for the key. Entries that can't be read are treated as missing,
+    /// so a corrupt cache only costs a re-parse.
+    pub fn get(&self, key: &str) -> Option<CacheEntry> {
+        self.mark_used(key);
+        let path = self.entry_path(key);
+        let contents = fs::read(&path).ok()?;
+        match serde_json::from_slice(&contents) {
+            Ok(entry) => Some(entry),
+            Err(e) => {
+                debug!("Ignoring unreadable cache entry {}: {}", path.display(), e);
+                None
+            }
+        }
+    }
+
+    /// Stores the entry for the key. The entry is written to a temporary file first, so
+    /// concurrent runs (and threads storing the same entry) never see a partially written
+    /// entry.
+    pub fn put(&self, key: &str, entry: &CacheEntry) -> Result<()> {
+        self.mark_used(key);
+        let path = self.entry_path(key);
+        let write_error = |e: &dyn std::fmt::Display| {
+            CacheError::CannotWriteEntry(path.display().to_string(), e.to_string())
+        };
+
+        let contents = serde_json::to_vec(entry).map_err(|e| write_error(&e))?;
+        let temp_number = self.next_temp_file.fetch_add(1, Ordering::Relaxed);
+        let temp_path = self.dir.join(format!(
+            "{}{}.{}.{}.tmp", FILE_PREFIX, key, std::process::id(), temp_number));
+        fs::write(&temp_path, contents)
+            .and_then(|_| fs::rename(&temp_path, &path))
+            .map_err(|e| {
+                let _ = fs::remove_file(&temp_path);
+                write_error(&e)
+            })
+    }
+
+    /// Removes the entries that weren't looked up or stored since the cache was opened, and
+    /// temporary files left behind by runs that were killed mid-write. Other files in the
+    /// directory are left alone. Returns the number of entries removed.
+    pub fn evict_unused(&self) -> Result<usize> {
+        let access_error = |e: std::io::Error| {
+            CacheError::CannotAccessCache(self.dir.display().to_string(), e.to_string())
+        };
+        let used_keys = self.used_keys.lock().unwrap();
+        let mut evicted = 0;
+        for entry in fs::read_dir(&self.dir).map_err(access_error)? {
+            let entry = entry.map_err(access_error)?;
+            let path = entry.path();
+            let Some(file_name) = path.file_name().and_then(|name| name.to_str()) else {
+                continue;
+            };
+            if let Some(key) = entry_key(file_name) {
+                if !used_keys.contains(key) {
+                    debug!("Evicting stale cache entry: {}", path.display());
+                    fs::remove_file(&path).map_err(access_error)?;
+                    evicted += 1;
+                }
+            } else if is_temp_file(file_name) && is_stale(&entry) {
+                debug!("Removing abandoned temporary file: {}", path.display());
+                // Another run may have removed it first
+                let _ = fs::remove_file(&path);
+            }
+        }
+        Ok(evicted)
+    }
+
+    fn mark_used(&self, key: &str) {
+        self.used_keys.lock().unwrap().insert(key.to_string());
+    }
+
+    fn entry_path(&self, key: &str) -> PathBuf {
+        self.dir.join(format!("{}{}.json", FILE_PREFIX, key))
+    }
+}
+
+/// Returns the key of a cache entry's file name, or `None` if the file isn't a cache entry.
+/// Keys are a SHA-256 hex digest and a version, as produced by `Cache::key`.
+fn entry_key(file_name: &str) -> Option<&str> {
+    let key = file_name.strip_prefix(FILE_PREFIX)?.strip_suffix(".json")?;
+    let (hash, version) = key.split_once('-')?;
+    let is_key = hash.len() == 64
+        && hash.bytes().all(|b| b.is_ascii_digit() || (b'a'..=b'f').contains(&b))
+        && !version.is_empty();
+    is_key.then_some(key)
+}
+
+fn is_temp_file(file_name: &str) -> bool {
+    file_name.starts_with(FILE_PREFIX) && file_name.ends_with(".tmp")
+}
+
+/// Whether a temporary file is old enough that the run writing it can't still be going.
+fn is_stale(entry: &fs::DirEntry) -> bool {
+    entry.metadata()
+        .and_then(|metadata| metadata.modified())
+        .ok()
+        .and_then(|modified| SystemTime::now().duration_since(modified).ok())
+        .is_some_and(|age| age > STALE_TEMP_FILE_AGE)
+}
+
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+    use std::time::SystemTime;
+    use tempdir::TempDir;
+    use mavir::{generate, manifest, parse};
+    use super::{Cache, CacheEntry, STALE_TEMP_FILE_AGE};
+
+    fn entry(source_code: &str) -> CacheEntry {
+        let parse_result = parse::parse_source("Value.java", source_code).unwrap();
+        let generated_files = generate::generate_sources(std::slice::from_ref(&parse_result));
+        CacheEntry { parse_result, generated_files }
+    }
+
+    const SOURCE: &str = r#"
+        package com.example;
+        import com.google.auto.value.AutoValue;
+        @AutoValue
+        abstract class Value {
+            abstract java.util.List<String> names();
+        }
+        "#;
+
+    fn key(source_code: &str) -> String {
+        Cache::key(&manifest::content_hash(source_code))
+    }
+
+    #[test]
+    fn cached_entries_round_trip() {
+        let dir = TempDir::new("mavir-cache").unwrap();
+        let cache = Cache::open(&dir.path().display().to_string()).unwrap();
+        let key = key(SOURCE);
+        assert!(cache.get(&key).is_none());
+
+        let entry = entry(SOURCE);
+        cache.put(&key, &entry).unwrap();
+        let cached = cache.get(&key).unwrap();
+        assert_eq!(cached.generated_files, entry.generated_files);
+        assert_eq!(format!("{:?}", cached.parse_result), format!("{:?}", entry.parse_result));
+    }
+
+    #[test]
+    fn same_entry_can_be_stored_concurrently() {
+        let dir = TempDir::new("mavir-cache").unwrap();
+        let cache = Cache::open(&dir.path().display().to_string()).unwrap();
+        let key = key(SOURCE);
+        let entry = entry(SOURCE);
+        std::thread::scope(|scope| {
+            for _ in 0..8 {
+                scope.spawn(|| cache.put(&key, &entry).unwrap());
+            }
+        });
+        assert_eq!(cache.get(&key).unwrap().generated_files, entry.generated_files);
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn unused_entries_are_evicted() {
+        let dir = TempDir::new("mavir-cache").unwrap();
+        let dir_str = dir.path().display().to_string();
+        let changed_source = SOURCE.replace("names", "labels");
+        let cache = Cache::open(&dir_str).unwrap();
+        cache.put(&key(SOURCE), &entry(SOURCE)).unwrap();
+        cache.put(&key(&changed_source), &entry(&changed_source)).unwrap();
+        assert_ne!(key(SOURCE), key(&changed_source));
+
+        // A later run only sees the changed source
+        let cache = Cache::open(&dir_str).unwrap();
+        assert!(cache.get(&key(&changed_source)).is_some());
+        assert_eq!(cache.evict_unused().unwrap(), 1);
+        assert!(cache.get(&key(SOURCE)).is_none());
+        assert!(cache.get(&key(&changed_source)).is_some());
+    }
+    #[test]
+    fn eviction_only_removes_mavir_files() {
+        let dir = TempDir::new("mavir-cache").unwrap();
+        let dir_str = dir.path().display().to_string();
+        let cache = Cache::open(&dir_str).unwrap();
+        cache.put(&key(SOURCE), &entry(SOURCE)).unwrap();
+        let unrelated = ["package.json", "mavir-settings.json", "notes.tmp"];
+        for name in unrelated {
+            fs::write(dir.path().join(name), "{}").unwrap();
+        }
+        let backdated = SystemTime::now() - STALE_TEMP_FILE_AGE * 2;
+        let abandoned = dir.path().join(format!("mavir-{}.1.0.tmp", key(SOURCE)));
+        let in_progress = dir.path().join(format!("mavir-{}.2.0.tmp", key(SOURCE)));
+        fs::write(&abandoned, "{").unwrap();
+        fs::File::options().write(true).open(&abandoned).unwrap().set_modified(backdated).unwrap();
+        fs::write(&in_progress, "{").unwrap();
+
+        let cache = Cache::open(&dir_str).unwrap();
+        assert_eq!(cache.evict_unused().unwrap(), 1);
+        for name in unrelated {
+            assert!(dir.path().join(name).exists(), "{} was removed", name);
+        }
+        assert!(!abandoned.exists());
+        assert!(in_progress.exists());
+    }
+}
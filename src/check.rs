@@ -0,0 +1,188 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs::File;
+use std::io::{Cursor, Read, Seek};
+
+use similar::TextDiff;
+use thiserror::Error;
+
+use crate::generate::{self, GenerateError, GeneratedFile};
+
+#[derive(Debug, Error)]
+pub enum CheckError {
+    #[error("Could not read archive {0}: {1}")]
+    CannotReadArchive(String, String),
+    #[error(transparent)]
+    GenerateError(#[from] GenerateError),
+}
+
+pub type Result<T> = std::result::Result<T, CheckError>;
+
+/// The differences between an existing source JAR and the one that would be generated.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct JarDifferences {
+    /// Entries that would be generated, but are missing from the existing archive
+    pub missing: Vec<String>,
+    /// Entries in the existing archive that would no longer be generated
+    pub extra: Vec<String>,
+    /// Entries whose contents differ
+    pub changed: Vec<ChangedEntry>,
+}
+
+/// An entry whose contents differ between the existing and the generated archive.
+#[derive(Debug, PartialEq, Eq)]
+pub struct ChangedEntry {
+    pub name: String,
+    /// A unified diff from the existing to the generated contents
+    pub diff: String,
+}
+
+impl JarDifferences {
+    pub fn is_empty(&self) -> bool {
+        self.missing.is_empty() && self.extra.is_empty() && self.changed.is_empty()
+    }
+}
+
+impl fmt::Display for JarDifferences {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for name in &self.missing {
+            writeln!(f, "Missing entry: {}", name)?;
+        }
+        for name in &self.extra {
+            writeln!(f, "Extra entry: {}", name)?;
+        }
+        for entry in &self.changed {
+            write!(f, "{}", entry.diff)?;
+        }
+        Ok(())
+    }
+}
+
+/// Compares the source JAR at the expected path with the one `write_jar` would write for the
+/// generated files, entry by entry. The archive is generated in memory, so nothing is written.
+pub fn check_jar(files: &[GeneratedFile], expected_path: &str) -> Result<JarDifferences> {
+    let mut generated = Cursor::new(vec![]);
+    generate::write_jar_entries(files, &mut generated)?;
+    let generated = read_entries(generated, "generated")?;
+
+    let file = File::open(expected_path)
+        .map_err(|e| CheckError::CannotReadArchive(expected_path.to_string(), e.to_string()))?;
+    let expected = read_entries(file, expected_path)?;
+    Ok(compare(&expected, &generated, expected_path))
+}
+
+/// Reads the contents of every file entry in the archive, by name. Directory entries are
+/// skipped, since they only depend on the files and tools differ in whether they write them.
+fn read_entries<R: Read + Seek>(reader: R, archive_path: &str) -> Result<BTreeMap<String, Vec<u8>>> {
+    let error = |e: &dyn fmt::Display| CheckError::CannotReadArchive(archive_path.to_string(), e.to_string());
+    let mut archive = zip::ZipArchive::new(reader).map_err(|e| error(&e))?;
+    let mut entries = BTreeMap::new();
+    for i in 0..archive.len() {
+        let mut entry = archive.by_index(i).map_err(|e| error(&e))?;
+        if entry.is_dir() {
+            continue;
+        }
+        let mut contents = vec![];
+        entry.read_to_end(&mut contents).map_err(|e| error(&e))?;
+        entries.insert(entry.name().to_string(), contents);
+    }
+    Ok(entries)
+}
+
+fn compare(
+    expected: &BTreeMap<String, Vec<u8>>,
+    generated: &BTreeMap<String, Vec<u8>>,
+    expected_path: &str,
+) -> JarDifferences {
+    let mut differences = JarDifferences::default();
+    for (name, generated_contents) in generated {
+        match expected.get(name) {
+            None => differences.missing.push(name.clone()),
+            Some(expected_contents) if expected_contents != generated_contents => {
+                let diff = match (std::str::from_utf8(expected_contents), std::str::from_utf8(generated_contents)) {
+                    (Ok(expected_text), Ok(generated_text)) => TextDiff::from_lines(expected_text, generated_text)
+                        .unified_diff()
+                        .header(&format!("{}!/{}", expected_path, name), &format!("generated/{}", name))
+                        .to_string(),
+                    _ => format!("Binary contents of {} differ\n", name),
+                };
+                differences.changed.push(ChangedEntry { name: name.clone(), diff });
+            }
+            Some(_) => {}
+        }
+    }
+    differences.extra = expected.keys()
+        .filter(|name| !generated.contains_key(*name))
+        .cloned()
+        .collect();
+    differences
+}
+
+
+#[cfg(test)]
+mod test {
+    use std::fs::File;
+    use tempdir::TempDir;
+    use crate::generate::{self, GeneratedFile};
+
+    fn file(path: &str, contents: &str) -> GeneratedFile {
+        GeneratedFile { path: path.to_string(), contents: contents.to_string() }
+    }
+
+    #[test]
+    fn reports_missing_extra_and_changed_entries() {
+        let dir = TempDir::new("mavir-check").unwrap();
+        let expected_path = dir.path().join("out.srcjar");
+        let expected_path = expected_path.to_str().unwrap();
+        generate::write_jar(&[
+            file("com/example/AutoValue_A.java", "class A {\n  int a;\n}\n"),
+            file("com/example/AutoValue_Old.java", "class Old {}\n"),
+        ], expected_path, false).unwrap();
+
+        let unchanged = super::check_jar(&[
+            file("com/example/AutoValue_A.java", "class A {\n  int a;\n}\n"),
+            file("com/example/AutoValue_Old.java", "class Old {}\n"),
+        ], expected_path).unwrap();
+        assert!(unchanged.is_empty());
+
+        let differences = super::check_jar(&[
+            file("com/example/AutoValue_A.java", "class A {\n  long a;\n}\n"),
+            file("com/other/AutoValue_New.java", "class New {}\n"),
+        ], expected_path).unwrap();
+        assert_eq!(differences.missing, vec!["com/other/AutoValue_New.java"]);
+        assert_eq!(differences.extra, vec!["com/example/AutoValue_Old.java"]);
+        assert_eq!(differences.changed.len(), 1);
+        assert_eq!(differences.changed[0].diff, format!(
+            "--- {0}!/com/example/AutoValue_A.java\n\
+             +++ generated/com/example/AutoValue_A.java\n\
+             @@ -1,3 +1,3 @@\n \
+             class A {{\n\
+             -  int a;\n\
+             +  long a;\n \
+             }}\n", expected_path));
+    }
+
+    #[test]
+    fn directory_entries_are_ignored() {
+        let dir = TempDir::new("mavir-check").unwrap();
+        let files = [file("com/example/AutoValue_A.java", "class A {}\n")];
+        let generated_path = dir.path().join("generated.srcjar");
+        generate::write_jar(&files, generated_path.to_str().unwrap(), false).unwrap();
+
+        // The same files, as written by a tool that adds different directory entries
+        let expected_path = dir.path().join("out.srcjar");
+        let mut generated = zip::ZipArchive::new(File::open(&generated_path).unwrap()).unwrap();
+        let mut writer = zip::ZipWriter::new(File::create(&expected_path).unwrap());
+        writer.add_directory("unrelated/", zip::write::SimpleFileOptions::default()).unwrap();
+        for i in 0..generated.len() {
+            let entry = generated.by_index(i).unwrap();
+            if !entry.is_dir() {
+                writer.raw_copy_file(entry).unwrap();
+            }
+        }
+        writer.finish().unwrap();
+
+        let differences = super::check_jar(&files, expected_path.to_str().unwrap()).unwrap();
+        assert!(differences.is_empty(), "{}", differences);
+    }
+}
@@ -0,0 +1,89 @@
+use std::collections::HashSet;
+use std::fs;
+
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum DepfileError {
+    #[error("Path can't be written to a depfile: {0:?}")]
+    UnsupportedPath(String),
+    #[error("Could not write depfile {0}: {1}")]
+    CannotWriteDepfile(String, String),
+}
+
+pub type Result<T> = std::result::Result<T, DepfileError>;
+
+/// Formats a GCC-style depfile (as understood by Make, Ninja and CMake) declaring that the
+/// target depends on each of the dependencies. Duplicate dependencies are only listed once.
+/// Paths containing line breaks can't be represented, and are rejected.
+pub fn format(target: &str, dependencies: &[String]) -> Result<String> {
+    let mut depfile = format!("{}:", escape(target)?);
+    let mut seen = HashSet::new();
+    for dependency in dependencies {
+        if seen.insert(dependency) {
+            depfile.push_str(" \\\n  ");
+            depfile.push_str(&escape(dependency)?);
+        }
+    }
+    depfile.push('\n');
+    Ok(depfile)
+}
+
+/// Writes the depfile (as for `format`) to the output path.
+pub fn write_depfile(target: &str, dependencies: &[String], output_path: &str) -> Result<()> {
+    fs::write(output_path, format(target, dependencies)?)
+        .map_err(|e| DepfileError::CannotWriteDepfile(output_path.to_string(), e.to_string()))
+}
+
+/// Escapes a path as GCC does: spaces, tabs and '#' are escaped with a backslash (doubling any
+/// backslashes right before them, so they aren't taken as escapes), and '$' is doubled.
+fn escape(path: &str) -> Result<String> {
+    if path.contains(['\n', '\r']) {
+        return Err(DepfileError::UnsupportedPath(path.to_string()));
+    }
+    let mut escaped = String::with_capacity(path.len());
+    let mut backslashes = 0;
+    for c in path.chars() {
+        match c {
+            '\\' => {
+                backslashes += 1;
+                continue;
+            }
+            ' ' | '\t' | '#' => {
+                escaped.push_str(&"\\".repeat(backslashes * 2 + 1));
+            }
+            '$' => {
+                escaped.push_str(&"\\".repeat(backslashes));
+                escaped.push('$');
+            }
+            _ => escaped.push_str(&"\\".repeat(backslashes)),
+        }
+        backslashes = 0;
+        escaped.push(c);
+    }
+    escaped.push_str(&"\\".repeat(backslashes));
+    Ok(escaped)
+}
+
+
+#[cfg(test)]
+mod test {
+    #[test]
+    fn paths_are_escaped_and_deduplicated() {
+        let dependencies = [
+            "src/Value.java",
+            "src/My Value.java",
+            "src/#1$.java",
+            r"C:\src\a\ b.java",
+            "src/Value.java",
+        ].map(String::from);
+        assert_eq!(super::format("out dir/out.srcjar", &dependencies).unwrap(), concat!(
+            "out\\ dir/out.srcjar: \\\n",
+            "  src/Value.java \\\n",
+            "  src/My\\ Value.java \\\n",
+            "  src/\\#1$$.java \\\n",
+            "  C:\\src\\a\\\\\\ b.java\n",
+        ));
+        assert!(super::format("out.srcjar", &["a\nb.java".to_string()]).is_err());
+    }
+}
@@ -0,0 +1,248 @@
+//! A C ABI for generating code from within other languages. The header for it,
+//! `include/mavir.h`, is generated by cbindgen from this file when the crate is built.
+//!
+//! Results are allocated by mavir and must be released with `mavir_result_free`. Strings are
+//! NUL-terminated UTF-8.
+
+use std::ffi::{c_char, CStr, CString};
+use std::panic::{self, AssertUnwindSafe};
+use std::ptr;
+
+use crate::generate::{self, GeneratedFile};
+use crate::parse::{self, Diagnostic};
+
+/// The severity of a diagnostic.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MavirSeverity {
+    /// The code was generated, but doesn't support a feature used by the source.
+    MavirWarning = 1,
+    /// No code could be generated for the source.
+    MavirError = 2,
+}
+
+/// A problem found in the source. Lines and columns are 1-based, and columns count bytes.
+/// Diagnostics that don't refer to a position in the source have a line of 0.
+#[repr(C)]
+pub struct MavirDiagnostic {
+    pub severity: MavirSeverity,
+    pub start_line: usize,
+    pub start_column: usize,
+    pub end_line: usize,
+    pub end_column: usize,
+    pub message: *mut c_char,
+}
+
+/// A generated source file, with a path relative to the root of the generated sources (such
+/// as `com/example/AutoValue_Foo.java`).
+#[repr(C)]
+pub struct MavirGeneratedFile {
+    pub path: *mut c_char,
+    pub contents: *mut c_char,
+}
+
+/// The files generated for a source, and the diagnostics found in it.
+#[repr(C)]
+pub struct MavirResult {
+    pub files: *mut MavirGeneratedFile,
+    pub files_len: usize,
+    pub diagnostics: *mut MavirDiagnostic,
+    pub diagnostics_len: usize,
+}
+
+/// Returns the version of mavir. The string is static and must not be freed.
+#[no_mangle]
+pub extern "C" fn mavir_version() -> *const c_char {
+    concat!(env!("CARGO_PKG_VERSION"), "\0").as_ptr().cast()
+}
+
+/// Generates the code for the AutoValue classes in a Java source file. `path_hint` identifies
+/// the source in diagnostics and need not exist; it may be NULL. `source` points to
+/// `source_len` bytes of UTF-8, which need not be NUL-terminated.
+///
+/// Always returns a result, which must be freed with `mavir_result_free`. Sources with syntax
+/// errors produce an error diagnostic rather than code generated from a partial parse.
+///
+/// # Safety
+///
+/// `path_hint` must be NULL or a NUL-terminated string, and `source` must be valid for reads
+/// of `source_len` bytes. Both are only read during the call.
+#[no_mangle]
+pub unsafe extern "C" fn mavir_generate(
+    path_hint: *const c_char,
+    source: *const u8,
+    source_len: usize,
+) -> *mut MavirResult {
+    let path_hint = if path_hint.is_null() {
+        String::new()
+    } else {
+        CStr::from_ptr(path_hint).to_string_lossy().into_owned()
+    };
+    let source = if source.is_null() { &[][..] } else { std::slice::from_raw_parts(source, source_len) };
+
+    // Unwinding across the C ABI is undefined behavior, so panics are reported as errors
+    let (files, diagnostics) = panic::catch_unwind(AssertUnwindSafe(|| generate(&path_hint, source)))
+        .unwrap_or_else(|_| (vec![], vec![error("Internal error in mavir")]));
+
+    let files = files.into_iter()
+        .map(|file| MavirGeneratedFile { path: to_c_string(&file.path), contents: to_c_string(&file.contents) })
+        .collect::<Vec<_>>();
+    let (files, files_len) = into_raw_parts(files);
+    let (diagnostics, diagnostics_len) = into_raw_parts(diagnostics);
+    Box::into_raw(Box::new(MavirResult { files, files_len, diagnostics, diagnostics_len }))
+}
+
+/// Frees a result returned by `mavir_generate`, along with all of its strings. Does nothing
+/// for NULL.
+///
+/// # Safety
+///
+/// `result` must be NULL or a result returned by `mavir_generate` that hasn't been freed yet.
+#[no_mangle]
+pub unsafe extern "C" fn mavir_result_free(result: *mut MavirResult) {
+    if result.is_null() {
+        return;
+    }
+    let result = Box::from_raw(result);
+    for file in from_raw_parts(result.files, result.files_len).iter() {
+        drop(CString::from_raw(file.path));
+        drop(CString::from_raw(file.contents));
+    }
+    for diagnostic in from_raw_parts(result.diagnostics, result.diagnostics_len).iter() {
+        drop(CString::from_raw(diagnostic.message));
+    }
+}
+
+fn generate(path_hint: &str, source: &[u8]) -> (Vec<GeneratedFile>, Vec<MavirDiagnostic>) {
+    let Ok(source_code) = std::str::from_utf8(source) else {
+        return (vec![], vec![error("Source is not valid UTF-8")]);
+    };
+    let tree = match parse::parse_tree(source_code, None) {
+        Ok(tree) => tree,
+        Err(e) => return (vec![], vec![error(&e.to_string())]),
+    };
+    let (parse_result, diagnostics) = match parse::collect_parse_result(&tree, source_code) {
+        Ok(result) => result,
+        Err(e) => return (vec![], vec![error(&format!("Could not process {}: {}", path_hint, e))]),
+    };
+    if !parse_result.class_declarations.is_empty() {
+        if let Some(syntax_error) = parse::syntax_error(&tree) {
+            return (vec![], vec![to_c_diagnostic(&syntax_error, MavirSeverity::MavirError)]);
+        }
+    }
+    let diagnostics = diagnostics.iter()
+        .map(|diagnostic| to_c_diagnostic(diagnostic, MavirSeverity::MavirWarning))
+        .collect();
+    (generate::generate_sources(std::slice::from_ref(&parse_result)), diagnostics)
+}
+
+fn error(message: &str) -> MavirDiagnostic {
+    MavirDiagnostic {
+        severity: MavirSeverity::MavirError,
+        start_line: 0,
+        start_column: 0,
+        end_line: 0,
+        end_column: 0,
+        message: to_c_string(message),
+    }
+}
+
+fn to_c_diagnostic(diagnostic: &Diagnostic, severity: MavirSeverity) -> MavirDiagnostic {
+    MavirDiagnostic {
+        severity,
+        start_line: diagnostic.start.row + 1,
+        start_column: diagnostic.start.column + 1,
+        end_line: diagnostic.end.row + 1,
+        end_column: diagnostic.end.column + 1,
+        message: to_c_string(&diagnostic.message),
+    }
+}
+
+/// Copies the string for C, dropping any interior NUL bytes (which C strings can't contain).
+fn to_c_string(s: &str) -> *mut c_char {
+    CString::new(s.replace('\0', "")).unwrap_or_default().into_raw()
+}
+
+/// Hands a vector over to C as a pointer and length. Empty vectors are passed as NULL.
+fn into_raw_parts<T>(items: Vec<T>) -> (*mut T, usize) {
+    if items.is_empty() {
+        return (ptr::null_mut(), 0);
+    }
+    let len = items.len();
+    (Box::into_raw(items.into_boxed_slice()).cast(), len)
+}
+
+/// Takes back ownership of a vector passed to C by `into_raw_parts`.
+unsafe fn from_raw_parts<T>(items: *mut T, len: usize) -> Box<[T]> {
+    if items.is_null() {
+        return Box::new([]);
+    }
+    Box::from_raw(ptr::slice_from_raw_parts_mut(items, len))
+}
+
+
+#[cfg(test)]
+mod test {
+    use std::ffi::CStr;
+    use super::MavirSeverity;
+
+    /// Runs `mavir_generate` on the source, returning the generated paths and the diagnostics
+    /// as `(severity, line, message)`
+    fn generate(source: &str) -> (Vec<String>, Vec<(MavirSeverity, usize, String)>) {
+        unsafe {
+            let result = super::mavir_generate(c"Value.java".as_ptr(), source.as_ptr(), source.len());
+            let paths = items((*result).files, (*result).files_len).iter()
+                .map(|f| CStr::from_ptr(f.path).to_string_lossy().into_owned())
+                .collect();
+            let diagnostics = items((*result).diagnostics, (*result).diagnostics_len).iter()
+                .map(|d| (d.severity, d.start_line, CStr::from_ptr(d.message).to_string_lossy().into_owned()))
+                .collect();
+            super::mavir_result_free(result);
+            (paths, diagnostics)
+        }
+    }
+
+    unsafe fn items<'a, T>(items: *const T, len: usize) -> &'a [T] {
+        if items.is_null() { &[] } else { std::slice::from_raw_parts(items, len) }
+    }
+
+    const SOURCE: &str = r#"
+        package com.example;
+        import com.google.auto.value.AutoValue;
+        @AutoValue
+        abstract class Value {
+            abstract String name();
+            @AutoValue.Builder
+            abstract static class Builder {}
+        }
+        "#;
+
+    #[test]
+    fn generates_files_with_diagnostics() {
+        let (paths, diagnostics) = generate(SOURCE);
+        assert_eq!(paths, vec!["com/example/AutoValue_Value.java"]);
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].0, MavirSeverity::MavirWarning);
+        assert_eq!(diagnostics[0].1, 8);
+        assert!(diagnostics[0].2.contains("@AutoValue.Builder"));
+    }
+
+    #[test]
+    fn syntax_errors_are_reported_without_generating_code() {
+        let (paths, diagnostics) = generate(&SOURCE.replace("name();", "name()"));
+        assert!(paths.is_empty());
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!((diagnostics[0].0, diagnostics[0].1), (MavirSeverity::MavirError, 6));
+
+        let (paths, diagnostics) = generate("");
+        assert!(paths.is_empty() && diagnostics.is_empty());
+        unsafe { super::mavir_result_free(std::ptr::null_mut()) };
+    }
+
+    #[test]
+    fn committed_header_is_up_to_date() {
+        let generated = concat!(env!("OUT_DIR"), "/mavir.h");
+        assert!(include_str!(concat!(env!("OUT_DIR"), "/mavir.h")) == include_str!("../include/mavir.h"),
+                "include/mavir.h is out of date; copy it from {}", generated);
+    }
+}
@@ -1,17 +1,24 @@
-use std::path::Path;
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::path::{Path, PathBuf};
 use std::io::prelude::*;
 use std::fs;
 use std::fs::File;
 use std::io::BufWriter;
-use crate::parse::{ClassDeclarationState, MethodDeclarationState, ParseResult};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use crate::modifiers::AccessLevel;
+use crate::parse::{ClassDeclarationState, ImportDeclaration, MethodDeclarationState, ParseResult};
+use crate::types::PrimitiveType;
+use crate::argfile;
+use crate::resolve;
 use crate::util;
 use crate::util::StripMargin;
 
 use log::debug;
-use tempdir::TempDir;
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
 use thiserror::Error;
-use walkdir::{DirEntry, WalkDir};
-use zip::DateTime;
+use walkdir::WalkDir;
+use zip::{CompressionMethod, DateTime};
 use zip::write::SimpleFileOptions;
 
 #[derive(Debug, Error)]
@@ -24,37 +31,151 @@ pub enum GenerateError {
     ZipError(String),
 }
 
-type Result<T> = std::result::Result<T, GenerateError>;
+pub type Result<T> = std::result::Result<T, GenerateError>;
 
-pub fn generate_code(parse_results: Vec<ParseResult>, output_path: &str) -> Result<()> {
-    let output_path = validate_output_path(output_path)?;
-    let working_dir = init_working_directory()?;
-    debug!("Writing to output path: {}", output_path);
+/// A generated source file. The path is relative to the root of the generated sources and
+/// always uses '/' as the separator, e.g. `com/example/AutoValue_Foo.java`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GeneratedFile {
+    pub path: String,
+    /// The Java source code of the file
+    pub contents: String,
+}
 
-    // Generate code
-    for parse_result in &parse_results {
-        for class_decl in &parse_result.class_declarations {
-            generate_java_file(
-                working_dir.path(),
+/// Generates the AutoValue classes for every class declaration. Files are generated in
+/// parallel, but returned in the order of the class declarations.
+pub fn generate_sources(parse_results: &[ParseResult]) -> Vec<GeneratedFile> {
+    parse_results
+        .par_iter()
+        .flat_map_iter(|parse_result| {
+            parse_result.class_declarations.iter().map(|class_decl| generate_java_file(
                 class_decl,
                 &parse_result.package_name,
                 &parse_result.import_statements,
-            )?;
-        }
+            ))
+        })
+        .collect()
+}
+
+/// Writes the generated files to a source JAR at the output path.
+pub fn write_jar(files: &[GeneratedFile], output_path: &str, force: bool) -> Result<()> {
+    let output_path = validate_output_path(output_path, force)?;
+    debug!("Writing to output path: {}", output_path);
+
+    // Package the generated code into a source JAR. The JAR is written to a temporary file
+    // next to the output path and renamed into place once complete, so a failure never
+    // leaves a partially written JAR behind.
+    let output_path = Path::new(&output_path);
+    let temp_path = temp_output_path(output_path)?;
+    let result = File::create(&temp_path)
+        .map_err(GenerateError::IoError)
+        .and_then(|output_file| write_jar_entries(files, BufWriter::new(output_file)))
+        .and_then(|_| fs::rename(&temp_path, output_path).map_err(GenerateError::IoError));
+    if result.is_err() {
+        let _ = fs::remove_file(&temp_path);
+    }
+    result
+}
+
+/// Numbers temporary JAR files, which must be unique between threads (such as concurrent
+/// worker requests) as well as processes
+static NEXT_TEMP_FILE: AtomicUsize = AtomicUsize::new(0);
+
+/// A path for the temporary file the JAR is written to before it's moved to the output path.
+/// It must be in the same directory, so the final rename is atomic.
+fn temp_output_path(output_path: &Path) -> Result<PathBuf> {
+    let file_name = output_path.file_name()
+        .ok_or(GenerateError::InvalidOutputPath(format!(
+            "Output path must name a file. Got: {}", output_path.display())))?;
+    let temp_number = NEXT_TEMP_FILE.fetch_add(1, Ordering::Relaxed);
+    let temp_name = format!(
+        ".{}.{}.{}.tmp", file_name.to_string_lossy(), std::process::id(), temp_number);
+    Ok(output_path.with_file_name(temp_name))
+}
+
+/// Writes the generated files as a directory tree (`package/AutoValue_X.java`) rather than a
+/// source JAR. Files whose contents are unchanged are left untouched, so their modification
+/// times stay stable, and files generated by earlier runs that are no longer generated are
+/// removed. When `sources_list` is given, the paths of the generated files are written there
+/// as a javac argument file (for `javac @sources.txt`).
+pub fn write_directory(
+    files: &[GeneratedFile],
+    output_dir: &str,
+    sources_list: Option<&str>,
+) -> Result<()> {
+    let output_dir = Path::new(output_dir);
+    if output_dir.exists() && !output_dir.is_dir() {
+        return Err(GenerateError::InvalidOutputPath(format!(
+            "Output directory is not a directory. Got: {}", output_dir.display())));
     }
+    debug!("Writing to output directory: {}", output_dir.display());
 
-    // Package the generated code into a source JAR
-    let output_file = File::create(Path::new(&output_path)).unwrap();
-    let walkdir = WalkDir::new(working_dir.path());
-    let it = walkdir.into_iter();
+    let mut generated_paths = BTreeSet::new();
+    for file in files {
+        let class_path = output_dir.join(&file.path);
+        write_if_changed(&class_path, file.contents.as_bytes())?;
+        generated_paths.insert(class_path);
+    }
+    remove_stale_files(output_dir, &generated_paths)?;
+
+    if let Some(sources_list) = sources_list {
+        let contents = generated_paths.iter()
+            .map(|path| format!("{}\n", argfile::quote(&path.to_string_lossy())))
+            .collect::<String>();
+        write_if_changed(Path::new(sources_list), contents.as_bytes())?;
+    }
+
+    Ok(())
+}
 
-    zip_dir(&mut it.filter_map(|e| e.ok()), working_dir.path(), output_file)?;
+/// Writes the file, unless it already exists with the same contents.
+fn write_if_changed(path: &Path, contents: &[u8]) -> Result<()> {
+    if fs::read(path).is_ok_and(|existing| existing == contents) {
+        debug!("Unchanged: {}", path.display());
+        return Ok(());
+    }
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(path, contents)?;
+    Ok(())
+}
 
+/// Removes the generated files (`AutoValue_*.java`) in the output directory that weren't
+/// generated by this run, along with any directories left empty. Other files are kept.
+fn remove_stale_files(output_dir: &Path, generated_paths: &BTreeSet<PathBuf>) -> Result<()> {
+    let stale_files = WalkDir::new(output_dir)
+        .into_iter()
+        .filter_map(|e| e.ok())
+        .filter(|e| e.file_type().is_file())
+        .map(|e| e.into_path())
+        .filter(|path| is_generated_file_name(path) && !generated_paths.contains(path))
+        .collect::<Vec<PathBuf>>();
+
+    for stale_file in stale_files {
+        debug!("Removing stale file: {}", stale_file.display());
+        fs::remove_file(&stale_file)?;
+        // Removing a directory fails once it isn't empty, which ends the clean-up
+        let mut dir = stale_file.parent();
+        while let Some(parent) = dir.filter(|d| *d != output_dir) {
+            if fs::remove_dir(parent).is_err() {
+                break;
+            }
+            dir = parent.parent();
+        }
+    }
     Ok(())
 }
 
-/// Check that the output path is valid
-fn validate_output_path(output_path: &str) -> Result<String> {
+fn is_generated_file_name(path: &Path) -> bool {
+    path.file_name()
+        .and_then(|name| name.to_str())
+        .is_some_and(|name| name.starts_with("AutoValue_") && name.ends_with(".java"))
+}
+
+/// Check that the output path is valid. An existing output is only replaced when `force`
+/// is set.
+fn validate_output_path(output_path: &str, force: bool) -> Result<String> {
     // Check if the output path ends in '.jar' or '.srcjar'
     if !output_path.ends_with(".jar") && !output_path.ends_with(".srcjar") {
         return Err(GenerateError::InvalidOutputPath(format!(
@@ -68,7 +189,10 @@ fn validate_output_path(output_path: &str) -> Result<String> {
         let current_dir = std::env::current_dir()
             .map_err(|e| GenerateError::InvalidOutputPath(format!("Failed to get current directory: {}", e)))?;
         let output_path = current_dir.join(output_path);
-        output_path.to_str().unwrap().to_string()
+        output_path.to_str()
+            .ok_or(GenerateError::InvalidOutputPath(format!(
+                "Output path is not valid UTF-8. Got: {}", output_path.display())))?
+            .to_string()
     };
 
     // Check if the parent directory exists
@@ -80,103 +204,97 @@ fn validate_output_path(output_path: &str) -> Result<String> {
             "Parent directory of output path does not exist. Got: {}", &output_path)));
     }
 
+    // Only ever replace an existing file, and only when asked to
+    let existing = Path::new(&output_path);
+    if existing.is_dir() {
+        return Err(GenerateError::InvalidOutputPath(format!(
+            "Output path is a directory. Got: {}", &output_path)));
+    }
+    if existing.exists() && !force {
+        return Err(GenerateError::InvalidOutputPath(format!(
+            "Output path already exists (use --force to overwrite it). Got: {}", &output_path)));
+    }
+
     Ok(output_path)
 }
 
-/// Create a temporary directory to write contents to. The contents of this directory
-/// will be the input to the packaged source JAR. This directory will be deleted after
-/// the source JAR is created.
-fn init_working_directory() -> Result<TempDir> {
-    let dir = TempDir::new("mavir")
-        .map_err(|e| GenerateError::IoError(e))?;
-
-    // Create a META-INF folder with a MANIFEST.MF file
-    let manifest_path = dir.path().join("META-INF/MANIFEST.MF");
-    fs::create_dir_all(manifest_path.parent().unwrap())
-        .map_err(|e| GenerateError::IoError(e))?;
-    fs::File::create(manifest_path)
-        .and_then(|mut f| f.write_all(b"Manifest-Version: 1.0\nCreated-By: mavir\n"))
-        .map_err(|e| GenerateError::IoError(e))?;
-
-    Ok(dir)
-}
+const MANIFEST: &str = "Manifest-Version: 1.0\nCreated-By: mavir\n";
 
-/// Zip a directory into a provided writer given a `walkdir` iterator and a prefix path (to strip
-/// from the zip file contents).
-fn zip_dir<T>(
-    it: &mut dyn Iterator<Item = DirEntry>,
-    prefix: &Path,
-    writer: T,
-) -> Result<()>
+/// Writes the generated files into a source JAR, along with a manifest and an entry for every
+/// directory. The archive only depends on the paths and contents of the files: entries are
+/// sorted canonically (with `META-INF/` first, as JAR tools expect), and timestamps,
+/// permissions and compression are fixed.
+pub(crate) fn write_jar_entries<T>(files: &[GeneratedFile], writer: T) -> Result<()>
 where
         T: Write + Seek,
 {
+    // Directory entries end in a '/', and have no contents
+    let mut entries: BTreeMap<(bool, &str), Option<&str>> = BTreeMap::new();
+    let manifest = GeneratedFile { path: "META-INF/MANIFEST.MF".to_string(), contents: MANIFEST.to_string() };
+    for file in files.iter().chain([&manifest]) {
+        let is_meta_inf = file.path.starts_with("META-INF/");
+        for (i, _) in file.path.match_indices('/') {
+            entries.insert((!is_meta_inf, &file.path[..=i]), None);
+        }
+        entries.insert((!is_meta_inf, &file.path), Some(&file.contents));
+    }
+
     let mut zip = zip::ZipWriter::new(writer);
+    let timestamp = DateTime::from_date_and_time(1980, 1, 1, 0, 0, 0)
+        .map_err(|e| GenerateError::ZipError(format!("Invalid timestamp: {}", e)))?;
     let options = SimpleFileOptions::default()
-        .last_modified_time(DateTime::from_date_and_time(1980, 1, 1, 0, 0, 0).unwrap());
-
-    let prefix = Path::new(prefix);
-    let mut buffer = Vec::new();
-    for entry in it {
-        let path = entry.path();
-        let name = path.strip_prefix(prefix).unwrap();
-        let path_as_string = name
-            .to_str()
-            .map(str::to_owned)
-            .ok_or(GenerateError::ZipError(format!("Path is not valid UTF-8: {:?}", &name)))?;
-
-        if path.is_file() {
-            debug!("adding file {path:?} as {name:?} ...");
-            zip.start_file(path_as_string, options)
-                .map_err(|e| GenerateError::ZipError(format!("Failed to start file: {}", e)))?;
-            let mut f = File::open(path)
-                .map_err(|e| GenerateError::ZipError(format!("Failed to open file: {}", e)))?;
-            f.read_to_end(&mut buffer)
-                .map_err(|e| GenerateError::ZipError(format!("Failed to read file: {}", e)))?;
-            zip.write_all(&buffer)
-                .map_err(|e| GenerateError::ZipError(format!("Failed to write file: {}", e)))?;
-            buffer.clear();
-        } else if !name.as_os_str().is_empty() {
-            // If not a file (and not the root), then it must be a directory
-            zip.add_directory(name.to_str().unwrap(), options)
-                .map_err(|e| GenerateError::ZipError(format!("Failed to add directory: {}", e)))?;
+        .last_modified_time(timestamp)
+        .compression_method(CompressionMethod::Deflated)
+        .compression_level(Some(6));
+    let file_options = options.unix_permissions(0o644);
+    let dir_options = options.unix_permissions(0o755);
+
+    for ((_, name), contents) in entries {
+        match contents {
+            Some(contents) => {
+                debug!("adding file {name:?} ...");
+                zip.start_file(name, file_options)
+                    .map_err(|e| GenerateError::ZipError(format!("Failed to start file: {}", e)))?;
+                zip.write_all(contents.as_bytes())
+                    .map_err(|e| GenerateError::ZipError(format!("Failed to write file: {}", e)))?;
+            }
+            None => {
+                zip.add_directory(name, dir_options)
+                    .map_err(|e| GenerateError::ZipError(format!("Failed to add directory: {}", e)))?;
+            }
         }
     }
 
-    zip.finish()
+    let mut writer = zip.finish()
         .map_err(|e| GenerateError::ZipError(format!("Failed to finish zip file: {}", e)))?;
+    writer.flush()?;
     Ok(())
 }
 
+/// Generates the source code of the AutoValue class, along with its path relative to the root
+/// of the generated sources.
 fn generate_java_file(
-    working_dir: &Path,
     class: &ClassDeclarationState,
     package_name: &str,
-    import_statements: &Vec<String>
-) -> Result<()>
+    import_statements: &[ImportDeclaration]
+) -> GeneratedFile
 {
     let class_name = get_class_name(class);
     let parent_class_name = get_parent_class_name(class);
 
-    let package_path = package_name.replace(".", "/");
-    let class_path = working_dir
-        .join(&package_path)
-        .join(format!("{}.java", class_name));
-
-    fs::create_dir_all(class_path.parent().unwrap())
-        .map_err(|e| GenerateError::IoError(e))?;
-    let mut file = fs::File::create(class_path)
-        .map_err(|e| GenerateError::IoError(e))?;
-    let mut writer = BufWriter::new(&mut file);
-    writer.write(template_file_contents(
+    let path = if package_name.is_empty() {
+        format!("{}.java", class_name)
+    } else {
+        format!("{}/{}.java", package_name.replace(".", "/"), class_name)
+    };
+    let contents = template_file_contents(
         &class_name,
         &parent_class_name,
         package_name,
         import_statements,
         class
-    ).as_bytes()).unwrap();
-
-    Ok(())
+    );
+    GeneratedFile { path, contents }
 }
 
 fn get_class_name(class: &ClassDeclarationState) -> String {
@@ -188,7 +306,7 @@ fn get_class_name(class: &ClassDeclarationState) -> String {
     class_name = format!("AutoValue_{class_name}");
 
     debug!("Generating code for {}", &class_name);
-    return class_name;
+    class_name
 }
 
 fn get_parent_class_name(class: &ClassDeclarationState) -> String {
@@ -196,19 +314,18 @@ fn get_parent_class_name(class: &ClassDeclarationState) -> String {
     for parent in &class.parent_chain {
         class_name = format!("{}.{}", parent, class_name);
     }
-    return class_name;
+    class_name
 }
 
 fn template_file_contents(
     class_name: &str,
     parent_class_name: &str,
     package_name: &str,
-    import_statements: &Vec<String>,
+    import_statements: &[ImportDeclaration],
     class: &ClassDeclarationState
 ) -> String {
-    let imports = import_statements.join("\n");
-
-    let modifiers = &class.modifiers.join(" ");
+    // The only modifier proxied to the generated class is the "public" access modifier
+    let modifiers = if class.modifiers.access == AccessLevel::Public { "public" } else { "" };
     let instance_vars = class.methods
         .iter()
         .map(template_instance_var_decls)
@@ -219,16 +336,14 @@ fn template_file_contents(
         .map(template_getter)
         .collect::<Vec<String>>()
         .join("\n");
+    let shadowed = shadowed_helpers(import_statements, class, &format!("{instance_vars}\n{getters}"));
+    let arrays = helper_name("Arrays", &shadowed);
     let constructor = template_constructor(class_name, class);
-    let to_string = template_to_string(parent_class_name, class);
-    let equals = template_equals(parent_class_name, class);
-    let hashcode = template_hashcode(class);
-
-    format!(r#"package {package_name};
-    |
-    |{imports}
-    |
-    |final {modifiers} class {class_name} extends {parent_class_name} {{
+    let to_string = template_to_string(parent_class_name, class, &arrays);
+    let equals = template_equals(class_name, parent_class_name, class, &arrays);
+    let hashcode = template_hashcode(class, &arrays);
+
+    let body = format!(r#"final {modifiers} class {class_name} extends {parent_class_name} {{
     |    {instance_vars}
     |    {constructor}
     |    {getters}
@@ -236,10 +351,160 @@ fn template_file_contents(
     |    {equals}
     |    {hashcode}
     |}}
-    |"#).strip_margin()
+    |"#).strip_margin();
+    let imports = required_imports(import_statements, &body, class, &shadowed);
+
+    // Classes in the default package are generated without a package declaration
+    let mut header = String::new();
+    if !package_name.is_empty() {
+        header.push_str(&format!("package {package_name};\n\n"));
+    }
+    if !imports.is_empty() {
+        header.push_str(&format!("{}\n\n", imports.join("\n")));
+    }
+
+    format!("{header}{body}")
+}
+
+/// Selects the import statements that the generated class body actually needs. Single-type
+/// and static imports are kept only when their simple name is referenced in the body. Wildcard
+/// imports are kept when the body references a type name that can't be accounted for
+/// otherwise, since it may be provided by one of them. `java.util` helpers used by the
+/// generated code are added when the source didn't already import them, unless they are
+/// shadowed (see `shadowed_helpers`) and so referenced fully-qualified.
+fn required_imports(
+    import_statements: &[ImportDeclaration],
+    body: &str,
+    class: &ClassDeclarationState,
+    shadowed_helpers: &[&str],
+) -> Vec<String> {
+    let referenced = referenced_names(body);
+    let is_helper = |name: &str| JAVA_UTIL_HELPERS.contains(&name) && !shadowed_helpers.contains(&name);
+
+    let imported: HashSet<&str> = import_statements.iter()
+        .filter_map(ImportDeclaration::simple_name)
+        .collect();
+    let needs_wildcard = referenced.iter().any(|name| {
+        name.starts_with(|c: char| c.is_ascii_uppercase())
+            && !imported.contains(name.as_str())
+            && !util::is_java_lang_type(name)
+            && *name != class.name
+            && !class.parent_chain.contains(name)
+            && !name.starts_with("AutoValue_")
+            && !is_helper(name)
+    });
+
+    let mut imports = import_statements.iter()
+        .filter(|import| match import.simple_name() {
+            Some(name) => referenced.contains(name),
+            None => needs_wildcard,
+        })
+        .map(ImportDeclaration::to_string)
+        .collect::<Vec<String>>();
+
+    for helper in JAVA_UTIL_HELPERS {
+        if is_helper(helper) && referenced.contains(helper) && !imported.contains(helper) {
+            imports.push(format!("import java.util.{};", helper));
+        }
+    }
+    imports
 }
 
-fn template_to_string(class_name: &str, class: &ClassDeclarationState) -> String {
+/// Types from `java.util` that the generated code may reference.
+const JAVA_UTIL_HELPERS: [&str; 2] = ["Arrays", "Objects"];
+
+/// Selects the `java.util` helpers whose simple name may mean a different type in the
+/// generated class: one imported from another package, the AutoValue class or one of its
+/// enclosing classes, or a type referenced by the properties (`user_code`) that isn't
+/// imported from `java.util`. The generated code refers to these helpers fully-qualified.
+fn shadowed_helpers(
+    import_statements: &[ImportDeclaration],
+    class: &ClassDeclarationState,
+    user_code: &str,
+) -> Vec<&'static str> {
+    let referenced = referenced_names(user_code);
+    JAVA_UTIL_HELPERS.into_iter()
+        .filter(|helper| {
+            let java_util_name = format!("java.util.{}", helper);
+            let mut imports = import_statements.iter()
+                .filter(|import| import.simple_name() == Some(*helper));
+            let imported_from_java_util = imports.clone().any(|import| import.name == java_util_name);
+            imports.any(|import| import.name != java_util_name)
+                || class.name == *helper
+                || class.parent_chain.iter().any(|parent| parent == helper)
+                || (referenced.contains(*helper) && !imported_from_java_util)
+        })
+        .collect()
+}
+
+/// The name the generated code uses for a `java.util` helper.
+fn helper_name(helper: &str, shadowed_helpers: &[&str]) -> String {
+    if shadowed_helpers.contains(&helper) {
+        format!("java.util.{}", helper)
+    } else {
+        helper.to_string()
+    }
+}
+
+/// Collects the names that could be resolved through an import: the first identifier of every
+/// (possibly qualified) name in the code. String literals and comments are skipped.
+fn referenced_names(code: &str) -> HashSet<String> {
+    let is_ident_char = |c: char| c.is_alphanumeric() || c == '_' || c == '$';
+
+    let mut names = HashSet::new();
+    let mut chars = code.char_indices().peekable();
+    let mut after_dot = false;
+    while let Some((start, c)) = chars.next() {
+        if c == '"' || c == '\'' {
+            let mut escaped = false;
+            for (_, next) in chars.by_ref() {
+                if escaped {
+                    escaped = false;
+                } else if next == '\\' {
+                    escaped = true;
+                } else if next == c {
+                    break;
+                }
+            }
+            after_dot = false;
+        } else if c == '/' && matches!(chars.peek(), Some((_, '/'))) {
+            for (_, next) in chars.by_ref() {
+                if next == '\n' {
+                    break;
+                }
+            }
+        } else if c == '/' && matches!(chars.peek(), Some((_, '*'))) {
+            chars.next();
+            let mut prev = ' ';
+            for (_, next) in chars.by_ref() {
+                if prev == '*' && next == '/' {
+                    break;
+                }
+                prev = next;
+            }
+        } else if is_ident_char(c) {
+            let mut end = start + c.len_utf8();
+            while let Some(&(i, next)) = chars.peek() {
+                if !is_ident_char(next) {
+                    break;
+                }
+                end = i + next.len_utf8();
+                chars.next();
+            }
+            if !after_dot && !c.is_ascii_digit() {
+                names.insert(code[start..end].to_string());
+            }
+            after_dot = false;
+        } else if c == '.' {
+            after_dot = true;
+        } else if !c.is_whitespace() {
+            after_dot = false;
+        }
+    }
+    names
+}
+
+fn template_to_string(class_name: &str, class: &ClassDeclarationState, arrays: &str) -> String {
 
     // The parent class-name is qualified for nested classes, such a 'OuterClass.InnerAutoValueClass'
     // But, for our toString method, we want just 'InnerAutoValueClass'
@@ -247,7 +512,14 @@ fn template_to_string(class_name: &str, class: &ClassDeclarationState) -> String
 
     let instance_vars = class.methods
         .iter()
-        .map(|m| format!(r#""{}=" + this.{}"#, m.name, m.name))
+        .map(|m| {
+            let name = &m.name;
+            if m.return_type.is_array() {
+                format!(r#""{name}=" + {arrays}.toString(this.{name})"#)
+            } else {
+                format!(r#""{name}=" + this.{name}"#)
+            }
+        })
         .collect::<Vec<String>>()
         .join(" + \", \"\n            + ");
 
@@ -261,25 +533,37 @@ fn template_to_string(class_name: &str, class: &ClassDeclarationState) -> String
     |"#).strip_margin()
 }
 
-fn template_equals(parent_class_name: &str, class: &ClassDeclarationState) -> String {
+fn template_equals(
+    class_name: &str,
+    parent_class_name: &str,
+    class: &ClassDeclarationState,
+    arrays: &str,
+) -> String {
 
     let equals_checks = class.methods
         .iter()
         .map(|m| {
             let name = &m.name;
-            if &m.return_type == "double" {
-                format!("Double.doubleToLongBits(this.{name}) == Double.doubleToLongBits(that.{name}())")
-            }
-            else if &m.return_type == "float" {
-                format!("Float.floatToIntBits(this.{name}) == Float.floatToIntBits(that.{name}())")
-            }
-            else if util::is_primitive_type(&m.return_type) {
-                format!("this.{name} == that.{name}()")
-            } else {
-                let if_null = if m.is_nullable {
-                    &format!("this.{name} == null? that.{name}() == null : ")
-                } else { "" };
-                format!("({if_null}this.{name}.equals(that.{name}()))")
+            match m.return_type.primitive() {
+                Some(PrimitiveType::Double) => {
+                    format!("Double.doubleToLongBits(this.{name}) == Double.doubleToLongBits(that.{name}())")
+                }
+                Some(PrimitiveType::Float) => {
+                    format!("Float.floatToIntBits(this.{name}) == Float.floatToIntBits(that.{name}())")
+                }
+                Some(_) => format!("this.{name} == that.{name}()"),
+                // Read the field directly when comparing against another generated instance,
+                // avoiding a defensive copy the accessor may make.
+                None if m.return_type.is_array() => {
+                    format!("{arrays}.equals(this.{name}, (that instanceof {class_name}) \
+                        ? (({class_name}) that).{name} : that.{name}())")
+                }
+                None => {
+                    let if_null = if m.is_nullable() {
+                        &format!("this.{name} == null? that.{name}() == null : ")
+                    } else { "" };
+                    format!("({if_null}this.{name}.equals(that.{name}()))")
+                }
             }
         })
         .collect::<Vec<String>>()
@@ -300,27 +584,29 @@ fn template_equals(parent_class_name: &str, class: &ClassDeclarationState) -> St
     |"#).strip_margin()
 }
 
-fn template_hashcode(class: &ClassDeclarationState) -> String {
+fn template_hashcode(class: &ClassDeclarationState, arrays: &str) -> String {
     let field_hashes = class.methods
         .iter()
         .map(|m| {
             let name = &m.name;
             let mult = "h$ *= 1000003;\n       ";
-            if &m.return_type == "long" {
-                format!("{mult} h$ ^= (int) (({name} >>> 32) ^ {name});")
-            } else if &m.return_type == "boolean" {
-                format!("{mult} h$ ^= {name} ? 1231 : 1237;")
-            } else if &m.return_type == "double" {
-                format!("{mult} h$ ^= (int) ((Double.doubleToLongBits({name}) >>> 32) ^ Double.doubleToLongBits({name}));")
-            } else if &m.return_type == "float" {
-                format!("{mult} h$ ^= Float.floatToIntBits({name});")
-            } else if util::is_primitive_type(&m.return_type) {
-                format!("{mult} h$ ^= this.{name};")
-            } else {
-                let if_null =
-                    if m.is_nullable { &format!("this.{name} == null ? 0 : ") }
-                    else { "" };
-                format!("{mult} h$ ^= {if_null}this.{name}.hashCode();")
+            match m.return_type.primitive() {
+                Some(PrimitiveType::Long) => {
+                    format!("{mult} h$ ^= (int) (({name} >>> 32) ^ {name});")
+                }
+                Some(PrimitiveType::Boolean) => format!("{mult} h$ ^= {name} ? 1231 : 1237;"),
+                Some(PrimitiveType::Double) => {
+                    format!("{mult} h$ ^= (int) ((Double.doubleToLongBits({name}) >>> 32) ^ Double.doubleToLongBits({name}));")
+                }
+                Some(PrimitiveType::Float) => format!("{mult} h$ ^= Float.floatToIntBits({name});"),
+                Some(_) => format!("{mult} h$ ^= this.{name};"),
+                None if m.return_type.is_array() => format!("{mult} h$ ^= {arrays}.hashCode({name});"),
+                None => {
+                    let if_null =
+                        if m.is_nullable() { &format!("this.{name} == null ? 0 : ") }
+                        else { "" };
+                    format!("{mult} h$ ^= {if_null}this.{name}.hashCode();")
+                }
             }
         })
         .collect::<Vec<String>>()
@@ -341,8 +627,11 @@ fn template_constructor(class_name: &str, class: &ClassDeclarationState) -> Stri
     let constructor_params = class.methods
         .iter()
         .map(|method| {
+            let nullable_annotation = method.modifiers.nullable_annotation()
+                .map(|a| format!("{a} "))
+                .unwrap_or_default();
             format!("{}{} {}",
-                    if method.is_nullable { "@Nullable " } else { "" },
+                    nullable_annotation,
                     method.return_type,
                     method.name)
         })
@@ -354,7 +643,7 @@ fn template_constructor(class_name: &str, class: &ClassDeclarationState) -> Stri
         .iter()
         .map(|method| {
             let name = &method.name;
-            if util::is_primitive_type(&method.return_type) || method.is_nullable {
+            if method.return_type.is_primitive() || method.is_nullable() {
                 format!(r#"        this.{name} = {name};"#)
             } else {
                 format!(r#"
@@ -379,7 +668,9 @@ fn template_constructor(class_name: &str, class: &ClassDeclarationState) -> Stri
 fn template_instance_var_decls(method: &MethodDeclarationState) -> String {
     let name = &method.name;
     let return_type = &method.return_type;
-    let nullable_annotation = if method.is_nullable { "@Nullable " } else { "" };
+    let nullable_annotation = method.modifiers.nullable_annotation()
+        .map(|a| format!("{a} "))
+        .unwrap_or_default();
     format!("{nullable_annotation}private final {return_type} {name};")
 }
 
@@ -387,10 +678,12 @@ fn template_getter(method: &MethodDeclarationState) -> String {
     let name = &method.name;
     let return_type = &method.return_type;
 
-    // Join all the modifiers except "abstract"
-    let modifiers = method.modifiers.iter()
-        .filter(|m| *m != "abstract")
-        .cloned()
+    // Carry over the annotations and access level of the abstract method. The accessor is
+    // always marked `@Override`, so the method's own is dropped rather than repeated.
+    let modifiers = method.modifiers.annotations.iter()
+        .filter(|a| a.qualified_name.as_deref() != Some(resolve::OVERRIDE))
+        .map(|a| a.to_string())
+        .chain(method.modifiers.access.keyword().map(str::to_string))
         .collect::<Vec<String>>()
         .join(" ");
 
@@ -411,4 +704,282 @@ fn template_getter(method: &MethodDeclarationState) -> String {
 //         None => String::new(),
 //         Some(first_char) => first_char.to_uppercase().chain(chars).collect(),
 //     }
-// }
\ No newline at end of file
+// }
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+    use std::io::Cursor;
+    use std::time::{Duration, SystemTime};
+    use tempdir::TempDir;
+    use crate::modifiers::{Modifiers, Span};
+    use crate::parse::{ClassDeclarationState, ImportDeclaration, MethodDeclarationState, ParseResult};
+    use crate::types::{JavaType, PrimitiveType};
+    use super::GeneratedFile;
+
+    fn import(name: &str, is_static: bool, is_wildcard: bool) -> ImportDeclaration {
+        ImportDeclaration { name: name.to_string(), is_static, is_wildcard }
+    }
+
+    fn class() -> ClassDeclarationState {
+        ClassDeclarationState {
+            name: "TestClass".to_string(),
+            ..Default::default()
+        }
+    }
+
+    fn parse_result(package_name: &str, class_names: &[&str]) -> ParseResult {
+        ParseResult {
+            package_name: package_name.to_string(),
+            import_statements: vec![],
+            class_declarations: class_names.iter()
+                .map(|name| ClassDeclarationState { name: name.to_string(), ..Default::default() })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn existing_jar_is_only_replaced_with_force() {
+        let dir = TempDir::new("mavir-output").unwrap();
+        let output_path = dir.path().join("out.srcjar");
+        let output_path_str = output_path.display().to_string();
+        fs::write(&output_path, "existing").unwrap();
+        let files = super::generate_sources(&[parse_result("com.example", &["Foo"])]);
+
+        let result = super::write_jar(&files, &output_path_str, false);
+        assert!(matches!(result, Err(super::GenerateError::InvalidOutputPath(_))));
+        assert_eq!(fs::read_to_string(&output_path).unwrap(), "existing");
+
+        super::write_jar(&files, &output_path_str, true).unwrap();
+        let archive = zip::ZipArchive::new(fs::File::open(&output_path).unwrap()).unwrap();
+        assert!(archive.file_names().any(|name| name == "com/example/AutoValue_Foo.java"));
+
+        // Only the output remains, without any temporary files next to it
+        let files = fs::read_dir(dir.path()).unwrap().map(|e| e.unwrap().file_name()).collect::<Vec<_>>();
+        assert_eq!(files, vec!["out.srcjar"]);
+    }
+
+    #[test]
+    fn override_annotations_are_not_repeated() {
+        let parse_result = crate::parse::parse_source("Value.java", r#"
+            package com.example;
+            import com.google.auto.value.AutoValue;
+            @AutoValue
+            abstract class Value implements HasName, HasId {
+                @Override @Deprecated public abstract String name();
+                @java.lang.Override abstract long id();
+            }
+            "#).unwrap();
+        let contents = &super::generate_sources(&[parse_result])[0].contents;
+        assert!(contents.contains("    @Override\n    @Deprecated public String name() {"));
+        assert!(!contents.contains("java.lang.Override"));
+        assert_eq!(contents.matches("Override").count(), 5);
+    }
+
+    #[test]
+    fn same_jar_can_be_written_concurrently() {
+        let dir = TempDir::new("mavir-output").unwrap();
+        let output_path = dir.path().join("out.srcjar").display().to_string();
+        let files = super::generate_sources(&[parse_result("com.example", &["Foo"])]);
+        std::thread::scope(|scope| {
+            for _ in 0..8 {
+                scope.spawn(|| super::write_jar(&files, &output_path, true).unwrap());
+            }
+        });
+        let archive = zip::ZipArchive::new(fs::File::open(&output_path).unwrap()).unwrap();
+        assert!(archive.file_names().any(|name| name == "com/example/AutoValue_Foo.java"));
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn jar_is_independent_of_file_order() {
+        let files = [
+            ("com/example/b/AutoValue_B.java", "class B {}"),
+            ("com/example/AutoValue_A.java", "class A {}"),
+            ("com/example/a/AutoValue_C.java", "class C {}"),
+        ].map(|(path, contents)| GeneratedFile { path: path.to_string(), contents: contents.to_string() });
+        let jar_bytes = |files: Vec<GeneratedFile>| {
+            let mut bytes = Cursor::new(vec![]);
+            super::write_jar_entries(&files, &mut bytes).unwrap();
+            bytes.into_inner()
+        };
+
+        let forward = jar_bytes(files.to_vec());
+        let reverse = jar_bytes(files.iter().rev().cloned().collect());
+        assert_eq!(forward, reverse);
+
+        let archive = zip::ZipArchive::new(Cursor::new(forward)).unwrap();
+        assert_eq!(archive.file_names().collect::<Vec<_>>(), vec![
+            "META-INF/",
+            "META-INF/MANIFEST.MF",
+            "com/",
+            "com/example/",
+            "com/example/AutoValue_A.java",
+            "com/example/a/",
+            "com/example/a/AutoValue_C.java",
+            "com/example/b/",
+            "com/example/b/AutoValue_B.java",
+        ]);
+    }
+
+    #[test]
+    fn directory_output_keeps_unchanged_files_and_removes_stale_ones() {
+        let dir = TempDir::new("mavir-output").unwrap();
+        let output_dir = dir.path().join("generated");
+        let output_dir_str = output_dir.display().to_string();
+        let sources_list = dir.path().join("sources.txt");
+        let sources_list_str = sources_list.display().to_string();
+        let kept = output_dir.join("com/example/AutoValue_Kept.java");
+        let removed = output_dir.join("com/example/other/AutoValue_Removed.java");
+
+        super::write_directory(
+            &super::generate_sources(&[
+                parse_result("com.example", &["Kept"]),
+                parse_result("com.example.other", &["Removed"]),
+            ]),
+            &output_dir_str,
+            Some(&sources_list_str),
+        ).unwrap();
+        assert!(kept.is_file() && removed.is_file());
+        fs::write(output_dir.join("com/example/Handwritten.java"), "").unwrap();
+
+        // Backdate the unchanged file, so a rewrite would be detected
+        let backdated = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000);
+        fs::File::options().write(true).open(&kept).unwrap().set_modified(backdated).unwrap();
+
+        super::write_directory(
+            &super::generate_sources(&[parse_result("com.example", &["Kept"])]),
+            &output_dir_str,
+            Some(&sources_list_str),
+        ).unwrap();
+        assert_eq!(fs::metadata(&kept).unwrap().modified().unwrap(), backdated);
+        assert!(!removed.exists());
+        assert!(!output_dir.join("com/example/other").exists());
+        assert!(output_dir.join("com/example/Handwritten.java").exists());
+        assert_eq!(fs::read_to_string(&sources_list).unwrap(), format!("{}\n", kept.display()));
+    }
+
+    #[test]
+    fn referenced_names_skips_qualified_segments_and_literals() {
+        let names = super::referenced_names(r#"
+            private final java.util.Map.Entry<String, List<Integer>> entry;
+            // Comment mentioning Optional
+            return "Set{" + this.entry + "}";
+        "#);
+        assert!(names.contains("java"));
+        assert!(names.contains("String"));
+        assert!(names.contains("List"));
+        assert!(names.contains("Integer"));
+        assert!(!names.contains("Map"));
+        assert!(!names.contains("Entry"));
+        assert!(!names.contains("Optional"));
+        assert!(!names.contains("Set"));
+    }
+
+    #[test]
+    fn unused_imports_are_dropped() {
+        let imports = vec![
+            import("com.google.auto.value.AutoValue", false, false),
+            import("java.util.Optional", false, false),
+            import("javax.annotation.Nullable", false, false),
+            import("com.google.common.base.Preconditions.checkNotNull", true, false),
+        ];
+        let body = "@Nullable private final Optional<String> name;";
+        assert_eq!(
+            super::required_imports(&imports, body, &class(), &[]),
+            vec!["import java.util.Optional;", "import javax.annotation.Nullable;"]);
+    }
+
+    #[test]
+    fn static_imports_are_kept_when_referenced() {
+        let imports = vec![import("com.example.Outer.Inner", true, false)];
+        let body = "private final Inner inner;";
+        assert_eq!(
+            super::required_imports(&imports, body, &class(), &[]),
+            vec!["import static com.example.Outer.Inner;"]);
+    }
+
+    #[test]
+    fn wildcard_imports_are_kept_only_for_unresolved_names() {
+        let imports = vec![
+            import("java.util", false, true),
+            import("com.example.Constants", true, true),
+        ];
+        let body = "private final String name; private final TestClass other;";
+        assert!(super::required_imports(&imports, body, &class(), &[]).is_empty());
+
+        let body = "private final List<String> names;";
+        assert_eq!(
+            super::required_imports(&imports, body, &class(), &[]),
+            vec!["import java.util.*;", "import static com.example.Constants.*;"]);
+    }
+
+    #[test]
+    fn java_util_helpers_are_added_when_used() {
+        let body = "return Arrays.equals(this.values, that.values());";
+        assert_eq!(
+            super::required_imports(&[], body, &class(), &[]),
+            vec!["import java.util.Arrays;"]);
+
+        let imports = vec![import("java.util.Arrays", false, false)];
+        assert_eq!(
+            super::required_imports(&imports, body, &class(), &[]),
+            vec!["import java.util.Arrays;"]);
+    }
+
+    #[test]
+    fn array_properties_use_arrays_helpers() {
+        let ints = MethodDeclarationState {
+            name: "ints".to_string(),
+            return_type: JavaType::Array {
+                component: Box::new(JavaType::Primitive {
+                    kind: PrimitiveType::Int,
+                    annotations: vec![],
+                }),
+                annotations: vec![],
+            },
+            modifiers: Modifiers::default(),
+            span: Span::default(),
+        };
+        let class = ClassDeclarationState { methods: vec![ints], ..class() };
+        let contents = super::template_file_contents(
+            "AutoValue_TestClass", "TestClass", "com.example", &[], &class);
+
+        assert!(contents.contains("import java.util.Arrays;"));
+        assert!(contents.contains("\"ints=\" + Arrays.toString(this.ints)"));
+        assert!(contents.contains("Arrays.equals(this.ints, "));
+        assert!(contents.contains("h$ ^= Arrays.hashCode(ints);"));
+    }
+
+    #[test]
+    fn shadowed_java_util_helpers_are_qualified() {
+        let imports_other_arrays = crate::parse::parse_source("Value.java", r#"
+            package com.example;
+            import com.google.auto.value.AutoValue;
+            import com.example.util.Arrays;
+            @AutoValue
+            abstract class Value {
+                abstract int[] ints();
+            }
+            "#).unwrap();
+        let contents = &super::generate_sources(&[imports_other_arrays])[0].contents;
+        assert!(!contents.contains("import"));
+        assert!(contents.contains("java.util.Arrays.equals(this.ints, "));
+        assert!(contents.contains("h$ ^= java.util.Arrays.hashCode(ints);"));
+
+        // A property type named 'Arrays' from the same package
+        let references_other_arrays = crate::parse::parse_source("Value.java", r#"
+            package com.example;
+            import com.google.auto.value.AutoValue;
+            @AutoValue
+            abstract class Value {
+                abstract int[] ints();
+                abstract Arrays arrays();
+            }
+            "#).unwrap();
+        let contents = &super::generate_sources(&[references_other_arrays])[0].contents;
+        assert!(!contents.contains("import"));
+        assert!(contents.contains("private final Arrays arrays;"));
+        assert!(contents.contains("java.util.Arrays.toString(this.ints)"));
+    }
+}
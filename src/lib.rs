@@ -0,0 +1,44 @@
+//! Generates the code for AutoValue classes directly from Java source files, without running
+//! javac or the AutoValue annotation processor.
+//!
+//! Source files are parsed into a [`ParseResult`] describing their AutoValue classes, from which
+//! the `AutoValue_*` classes are generated as [`GeneratedFile`]s. The generated files can then be
+//! packaged as a source JAR (or written to a directory), with output that only depends on the
+//! inputs.
+//!
+//! ```
+//! let source_code = r#"
+//!     package com.example;
+//!     import com.google.auto.value.AutoValue;
+//!     @AutoValue
+//!     abstract class Value {
+//!         abstract String name();
+//!     }
+//!     "#;
+//! let parse_result = mavir::parse_source("com/example/Value.java", source_code)?;
+//! assert_eq!(parse_result.class_declarations[0].name, "Value");
+//!
+//! let files = mavir::generate_sources(&[parse_result]);
+//! assert_eq!(files[0].path, "com/example/AutoValue_Value.java");
+//! # Ok::<(), mavir::parse::ParseError>(())
+//! ```
+//!
+//! Parsing and generation are independent between files, so callers may parse files in
+//! parallel (each thread reuses its own parser).
+
+pub mod argfile;
+pub mod check;
+pub mod depfile;
+pub mod ffi;
+pub mod generate;
+pub mod manifest;
+pub mod model;
+pub mod modifiers;
+pub mod parse;
+pub mod sources;
+pub mod types;
+mod resolve;
+mod util;
+
+pub use generate::{generate_sources, write_directory, write_jar, GeneratedFile};
+pub use parse::{parse_source, ClassDeclarationState, ImportDeclaration, MethodDeclarationState, ParseResult};
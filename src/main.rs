@@ -1,55 +1,399 @@
-mod parse;
-mod generate;
-mod util;
+mod cache;
+mod server;
+mod watch;
+mod worker;
 
-use anyhow::{anyhow, Result};
+use std::collections::HashSet;
+use std::ffi::OsString;
+use std::time::Duration;
+
+use anyhow::{anyhow, Context, Result};
 use clap::Parser;
-use env_logger;
-use log::{info, LevelFilter};
-use crate::parse::ParseResult;
+use log::{debug, info, LevelFilter};
+use rayon::prelude::*;
+use mavir::{argfile, check, depfile, generate, manifest, model, parse, sources};
+use mavir::manifest::{ManifestFormat, ManifestInput};
+use mavir::parse::ParseResult;
+use mavir::sources::ArchiveSource;
+use crate::cache::{Cache, CacheEntry};
 
-/// Generates AutoValue classes for given Java files. Outputs generated code as a source JAR.
+/// Generates AutoValue classes for given Java files. Outputs generated code as a source JAR or
+/// a directory of source files.
 #[derive(clap::Parser, Debug)]
-#[clap(version, about, long_about = None)]
+#[clap(version, about, long_about = None, after_help = ARGFILE_HELP)]
+#[clap(subcommand_negates_reqs = true)]
 struct Args {
-    /// Path to a Java source file.
-    #[arg(short, long)]
-    file_path: Vec<String>,
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    #[command(flatten)]
+    inputs: InputArgs,
 
     /// Path to the output file that will contain the generated code. This should be
-    /// a path to a source JAR. The path MUST not exist, but the parent directory is
-    /// expected to exist.
+    /// a path to a source JAR. The path must not exist unless --force is given, and the
+    /// parent directory is expected to exist.
+    #[arg(short, long, required_unless_present_any = ["output_dir", "persistent_worker"], conflicts_with = "output_dir")]
+    output_path: Option<String>,
+
+    /// Overwrite the output file if it already exists.
+    #[arg(long, requires = "output_path")]
+    force: bool,
+
+    /// Path to a directory to write the generated code to as a source tree, instead of a
+    /// source JAR. Unchanged files are left untouched and stale generated files are removed.
+    #[arg(long)]
+    output_dir: Option<String>,
+
+    /// Path to write the list of generated files to, for use as a javac argument file
+    /// (e.g. 'javac @sources.txt'). Requires --output-dir.
+    #[arg(long, requires = "output_dir")]
+    sources_list: Option<String>,
+
+    /// Directory to cache parse results and generated code in, keyed by the contents of each
+    /// input file. Unchanged files are then not parsed again. Entries for files that are no
+    /// longer inputs are removed, so the directory shouldn't be shared between targets.
+    #[arg(long)]
+    cache_dir: Option<String>,
+
+    /// Path to write the parsed AutoValue model (packages, imports, classes and their methods)
+    /// to as JSON, for debugging or for use by other tools. See the README for the schema.
+    #[arg(long)]
+    emit_model: Option<String>,
+
+    /// Path to write a manifest to, listing each input with the hash of its contents, its
+    /// AutoValue classes and the files generated for them.
+    #[arg(long)]
+    manifest_out: Option<String>,
+
+    /// Format of the manifest: 'json', or 'proto' for Bazel's 'Manifest' message (from
+    /// 'java_compilation.proto') listing the generated files.
+    #[arg(long, value_name = "FORMAT", value_parser = ["json", "proto"], default_value = "json", requires = "manifest_out")]
+    manifest_format: String,
+
+    /// Path to write a Make-style depfile to, listing every file that was read (including
+    /// argument files) as a dependency of the output path or directory. Lets Make, Ninja and
+    /// CMake rebuild the output when any of its inputs change.
+    #[arg(long)]
+    depfile: Option<String>,
+
+    /// Number of files to process in parallel. Defaults to the number of CPUs.
     #[arg(short, long)]
-    output_path: String,
+    jobs: Option<usize>,
+
+    /// Run as a Bazel persistent worker, reading work requests from stdin and writing responses
+    /// to stdout. Each request's arguments are handled like a separate invocation of mavir.
+    #[arg(long = "persistent_worker")]
+    persistent_worker: bool,
 
     /// Print Verbose output. This can also be configured with 'RUST_LOG=debug'
-    #[arg(short, long)]
+    #[arg(short, long, global = true)]
     verbose: bool,
 }
 
+/// The Java source files to generate code for.
+#[derive(clap::Args, Debug)]
+struct InputArgs {
+    /// Path to a Java source file.
+    #[arg(short, long)]
+    file_path: Vec<String>,
+
+    /// Path to a directory that is recursively scanned for Java source files.
+    #[arg(short, long)]
+    source_dir: Vec<String>,
+
+    /// Path to a source JAR whose Java source files are read directly from the archive.
+    #[arg(long)]
+    input_srcjar: Vec<String>,
+
+    /// Glob pattern for files to include when scanning a source directory or source JAR,
+    /// relative to that directory or archive (e.g. 'com/example/**'). Defaults to all '.java'
+    /// files.
+    #[arg(long)]
+    include: Vec<String>,
+
+    /// Glob pattern for files to exclude when scanning a source directory or source JAR,
+    /// relative to that directory or archive (e.g. '**/*Test.java').
+    #[arg(long)]
+    exclude: Vec<String>,
+}
+
+#[derive(clap::Subcommand, Debug)]
+enum Command {
+    /// Watches source directories and keeps an output directory of generated code up to date.
+    Watch(WatchArgs),
+    /// Serves the generated code for open (and unsaved) documents to an editor, using JSON-RPC
+    /// over stdin and stdout.
+    Serve,
+    /// Checks that an existing source JAR is up to date with its inputs, without writing
+    /// anything. Prints a diff of each differing file and exits with an error if it isn't.
+    Check(CheckArgs),
+}
+
+#[derive(clap::Args, Debug)]
+struct WatchArgs {
+    /// Path to a directory that is recursively scanned and watched for Java source files.
+    #[arg(short, long, required = true)]
+    source_dir: Vec<String>,
+
+    /// Glob pattern for files to include, relative to the source directory. Defaults to all
+    /// '.java' files.
+    #[arg(long)]
+    include: Vec<String>,
+
+    /// Glob pattern for files to exclude, relative to the source directory.
+    #[arg(long)]
+    exclude: Vec<String>,
+
+    /// Path to the directory to write the generated code to.
+    #[arg(long)]
+    output_dir: String,
+
+    /// How long to wait for further changes before regenerating, in milliseconds.
+    #[arg(long, default_value_t = 200)]
+    debounce_ms: u64,
+}
+
+#[derive(clap::Args, Debug)]
+struct CheckArgs {
+    #[command(flatten)]
+    inputs: InputArgs,
+
+    /// Path to the source JAR to compare with the generated code.
+    #[arg(long)]
+    expected: String,
+}
+
+const ARGFILE_HELP: &str = "Arguments may also be read from files by passing '@path/to/file'. \
+    Argument files follow javac's quoting rules and may reference other argument files.";
 
 fn main() -> Result<()> {
-    // Parse the CLI arguments and configure the log-level
-    let args = Args::parse();
+    // Parse the CLI arguments (expanding any @argfiles) and configure the log-level
+    let (arguments, argfiles) = argfile::expand_args_with_files(std::env::args_os())?;
+    let args = Args::parse_from(arguments);
     let mut builder = env_logger::builder();
     if args.verbose {
         builder.filter_level(LevelFilter::Debug);
-        println!("{:?}", args);
     }
     builder.init();
+    debug!("{:?}", args);
+
+    rayon::ThreadPoolBuilder::new()
+        .num_threads(args.jobs.unwrap_or(0))
+        .build_global()?;
+
+    match &args.command {
+        Some(Command::Watch(watch_args)) => {
+            let watch = watch::Watch::new(
+                &watch_args.source_dir, &watch_args.include, &watch_args.exclude, &watch_args.output_dir);
+            watch.run(Duration::from_millis(watch_args.debounce_ms))?;
+            return Ok(());
+        }
+        Some(Command::Serve) => {
+            server::serve(std::io::stdin().lock(), std::io::stdout())?;
+            return Ok(());
+        }
+        Some(Command::Check(check_args)) => return check(check_args),
+        None => {}
+    }
+    if args.persistent_worker {
+        worker::serve(std::io::stdin().lock(), std::io::stdout(), handle_work_request)?;
+        return Ok(());
+    }
+    generate(&args, &argfiles)
+}
+
+/// Handles a request from Bazel, whose arguments (including any @argfiles) are those of a
+/// regular invocation. Parsers, queries and the thread pool are shared between requests.
+fn handle_work_request(arguments: &[String]) -> (i32, String) {
+    let arguments = std::iter::once(OsString::from("mavir"))
+        .chain(arguments.iter().map(OsString::from));
+    let result = argfile::expand_args_with_files(arguments)
+        .map_err(anyhow::Error::from)
+        .and_then(|(arguments, argfiles)| {
+            let args = Args::try_parse_from(arguments)?;
+            if args.command.is_some() || args.persistent_worker {
+                return Err(anyhow!("Work requests can only generate code"));
+            }
+            generate(&args, &argfiles)
+        });
+    match result {
+        Ok(()) => (0, String::new()),
+        Err(e) => (1, format!("Error: {:#}", e)),
+    }
+}
 
-    if args.file_path.is_empty() {
-        return Err(anyhow!("Must specify at least one --file-path option"));
+/// Generates the code for the inputs given by the arguments, which were read from the given
+/// argument files (if any).
+fn generate(args: &Args, argfiles: &[String]) -> Result<()> {
+    let inputs = collect_inputs(&args.inputs)?;
+    let cache = args.cache_dir.as_deref().map(Cache::open).transpose()?;
+    let processed = process_inputs(&inputs, cache.as_ref(), args.manifest_out.is_some())?;
+    if let Some(model_path) = &args.emit_model {
+        let parse_results = inputs.iter()
+            .zip(&processed)
+            .map(|(input, processed)| {
+                (input.path(), processed.parsed.then_some(&processed.entry.parse_result))
+            })
+            .collect::<Vec<_>>();
+        model::write_model(&parse_results, model_path)?;
     }
+    if let Some(manifest_path) = &args.manifest_out {
+        let manifest_inputs = inputs.iter()
+            .zip(&processed)
+            .map(|(input, processed)| ManifestInput {
+                path: input.path(),
+                sha256: processed.sha256.as_deref().expect("inputs are hashed for the manifest"),
+                parse_result: &processed.entry.parse_result,
+                generated_files: &processed.entry.generated_files,
+            })
+            .collect::<Vec<_>>();
+        let format = match args.manifest_format.as_str() {
+            "proto" => ManifestFormat::Proto,
+            _ => ManifestFormat::Json,
+        };
+        manifest::write_manifest(&manifest_inputs, format, manifest_path)?;
+    }
+    let generated_files = processed
+        .into_iter()
+        .flat_map(|processed| processed.entry.generated_files)
+        .collect::<Vec<_>>();
 
-    let parse_results = args.file_path
-        .iter()
-        .map(|file_path| {
-            info!("Generating code for: {}", file_path);
-            parse::parse_file(file_path)
-        })
-        .collect::<parse::Result<Vec<ParseResult>>>()?;
-    generate::generate_code(parse_results, &args.output_path)?;
+    match (&args.output_path, &args.output_dir) {
+        (Some(output_path), _) => generate::write_jar(&generated_files, output_path, args.force)?,
+        (None, Some(output_dir)) => generate::write_directory(
+            &generated_files, output_dir, args.sources_list.as_deref())?,
+        (None, None) => unreachable!("clap requires an output path or output directory"),
+    }
+    if let Some(depfile_path) = &args.depfile {
+        // Every file that was read, other than cache entries: argument files, input files and
+        // source JARs (whose entries can't be dependencies themselves)
+        let dependencies = argfiles.iter()
+            .chain(inputs.iter().filter_map(|input| match input {
+                Input::File(file_path) => Some(file_path),
+                Input::ArchiveEntry(_) => None,
+            }))
+            .chain(&args.inputs.input_srcjar)
+            .cloned()
+            .collect::<Vec<String>>();
+        let target = args.output_path.as_ref().or(args.output_dir.as_ref())
+            .expect("clap requires an output path or output directory");
+        depfile::write_depfile(target, &dependencies, depfile_path)?;
+    }
+    if let Some(cache) = cache {
+        let evicted = cache.evict_unused()?;
+        debug!("Evicted {} stale cache entries", evicted);
+    }
 
     Ok(())
 }
+
+/// Compares the source JAR that would be generated for the inputs with the expected one,
+/// printing the differences.
+fn check(args: &CheckArgs) -> Result<()> {
+    let inputs = collect_inputs(&args.inputs)?;
+    let generated_files = process_inputs(&inputs, None, false)?
+        .into_iter()
+        .flat_map(|processed| processed.entry.generated_files)
+        .collect::<Vec<_>>();
+    let differences = check::check_jar(&generated_files, &args.expected)?;
+    if !differences.is_empty() {
+        print!("{}", differences);
+        return Err(anyhow!("{} is out of date", args.expected));
+    }
+    Ok(())
+}
+
+/// Lists the inputs given by the arguments: explicit files first (in the order given), followed
+/// by any scanned files not already listed, then the entries of source JARs.
+fn collect_inputs(args: &InputArgs) -> Result<Vec<Input>> {
+    if args.file_path.is_empty() && args.source_dir.is_empty() && args.input_srcjar.is_empty() {
+        return Err(anyhow!("Must specify at least one --file-path, --source-dir or --input-srcjar option"));
+    }
+
+    let mut file_paths = args.file_path.clone();
+    let explicit_paths: HashSet<String> = args.file_path.iter().cloned().collect();
+    file_paths.extend(
+        sources::collect_source_files(&args.source_dir, &args.include, &args.exclude)?
+            .into_iter()
+            .filter(|f| !explicit_paths.contains(f)));
+
+    // Source JAR entries are parsed straight from the archive, and are identified in
+    // diagnostics as 'archive!/entry'
+    let mut inputs = file_paths.into_iter().map(Input::File).collect::<Vec<Input>>();
+    for archive_path in &args.input_srcjar {
+        inputs.extend(
+            sources::read_archive_sources(archive_path, &args.include, &args.exclude)?
+                .into_iter()
+                .map(Input::ArchiveEntry));
+    }
+    Ok(inputs)
+}
+
+/// Processes the inputs in parallel, but keeps the results (and the first error) in input order
+/// so the output doesn't depend on scheduling. Inputs are hashed if `hash` is set (or to look
+/// them up in the cache).
+fn process_inputs(inputs: &[Input], cache: Option<&Cache>, hash: bool) -> Result<Vec<Processed>> {
+    inputs
+        .par_iter()
+        .map(|input| input.process(cache, hash))
+        .collect::<Vec<Result<Processed>>>()
+        .into_iter()
+        .collect()
+}
+
+/// A Java source file to generate code for.
+enum Input {
+    File(String),
+    ArchiveEntry(ArchiveSource),
+}
+
+/// The results of processing an input.
+struct Processed {
+    /// The hash of the input's contents, if it was hashed
+    sha256: Option<String>,
+    /// False if the input was skipped without parsing because it can't contain an AutoValue
+    /// class, in which case `entry` has an empty parse result
+    parsed: bool,
+    entry: CacheEntry,
+}
+
+impl Input {
+    fn path(&self) -> &str {
+        match self {
+            Input::File(file_path) => file_path,
+            Input::ArchiveEntry(source) => &source.path,
+        }
+    }
+
+    /// Parses the file and generates its code, unless the results for the file's contents
+    /// are already cached.
+    fn process(&self, cache: Option<&Cache>, hash: bool) -> Result<Processed> {
+        let path = self.path();
+        let source_code = match self {
+            Input::File(file_path) => parse::read_file(file_path)?,
+            Input::ArchiveEntry(source) => source.source_code.clone(),
+        };
+
+        let sha256 = (hash || cache.is_some()).then(|| manifest::content_hash(&source_code));
+        if !parse::may_contain_autovalue(&source_code) {
+            debug!("Skipping file without AutoValue classes: {}", path);
+            let entry = CacheEntry { parse_result: ParseResult::default(), generated_files: vec![] };
+            return Ok(Processed { sha256, parsed: false, entry });
+        }
+        let key = sha256.as_deref().map(Cache::key);
+        if let Some(entry) = cache.zip(key.as_ref()).and_then(|(cache, key)| cache.get(key)) {
+            debug!("Using cached results for: {}", path);
+            return Ok(Processed { sha256, parsed: true, entry });
+        }
+
+        info!("Generating code for: {}", path);
+        let parse_result = parse::parse_source(path, &source_code)
+            .with_context(|| format!("Could not process {}", path))?;
+        let generated_files = generate::generate_sources(std::slice::from_ref(&parse_result));
+        let entry = CacheEntry { parse_result, generated_files };
+        if let Some((cache, key)) = cache.zip(key.as_ref()) {
+            cache.put(key, &entry)?;
+        }
+        Ok(Processed { sha256, parsed: true, entry })
+    }
+}
@@ -0,0 +1,201 @@
+use std::fs;
+
+use prost::Message;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use thiserror::Error;
+
+use crate::generate::GeneratedFile;
+use crate::parse::{ClassDeclarationState, ParseResult};
+
+#[derive(Debug, Error)]
+pub enum ManifestError {
+    #[error("Could not write manifest to {0}: {1}")]
+    CannotWriteManifest(String, String),
+}
+
+pub type Result<T> = std::result::Result<T, ManifestError>;
+
+/// The encodings a manifest can be written in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ManifestFormat {
+    /// A JSON document listing each input with its classes and their generated files
+    Json,
+    /// Bazel's `Manifest` message from `java_compilation.proto`, listing the generated files
+    Proto,
+}
+
+/// An input file, along with the results of processing it.
+#[derive(Debug)]
+pub struct ManifestInput<'a> {
+    pub path: &'a str,
+    /// The hash of the input's contents, from `content_hash`
+    pub sha256: &'a str,
+    pub parse_result: &'a ParseResult,
+    /// The files generated for the parse result, one for each class declaration
+    pub generated_files: &'a [GeneratedFile],
+}
+
+#[derive(Serialize)]
+struct JsonManifest<'a> {
+    inputs: Vec<JsonInput<'a>>,
+}
+
+#[derive(Serialize)]
+struct JsonInput<'a> {
+    path: &'a str,
+    sha256: &'a str,
+    classes: Vec<JsonClass<'a>>,
+}
+
+#[derive(Serialize)]
+struct JsonClass<'a> {
+    /// The fully-qualified name of the `@AutoValue` class
+    name: String,
+    /// The fully-qualified name of the generated class
+    generated_class: String,
+    /// The path of the generated source file within the output
+    path: &'a str,
+}
+
+/// Bazel's `blaze_deps.Manifest`, listing the compilation units of a Java compilation.
+#[derive(Clone, PartialEq, Message)]
+struct Manifest {
+    #[prost(message, repeated, tag = "1")]
+    compilation_unit: Vec<CompilationUnit>,
+}
+
+/// Bazel's `blaze_deps.CompilationUnit`, a single source file of a Java compilation.
+#[derive(Clone, PartialEq, Message)]
+struct CompilationUnit {
+    #[prost(string, optional, tag = "1")]
+    path: Option<String>,
+    #[prost(string, optional, tag = "2")]
+    pkg: Option<String>,
+    #[prost(bool, optional, tag = "3")]
+    generated_by_annotation_processor: Option<bool>,
+    #[prost(string, repeated, tag = "4")]
+    top_level: Vec<String>,
+}
+
+/// Returns the SHA-256 hash of the source code, as lowercase hex.
+pub fn content_hash(source_code: &str) -> String {
+    Sha256::digest(source_code.as_bytes()).iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Encodes the manifest of the inputs, in input order. The JSON form maps each input (with the
+/// hash of its contents) to its `@AutoValue` classes and the files generated for them. The
+/// proto form lists each generated file as a compilation unit generated by an annotation
+/// processor, as javac does for the classes generated by AutoValue.
+pub fn encode(inputs: &[ManifestInput], format: ManifestFormat) -> Vec<u8> {
+    match format {
+        ManifestFormat::Json => {
+            let inputs = inputs.iter()
+                .map(|input| JsonInput {
+                    path: input.path,
+                    sha256: input.sha256,
+                    classes: input.parse_result.class_declarations.iter()
+                        .zip(input.generated_files)
+                        .map(|(class, file)| JsonClass {
+                            name: qualified_class_name(&input.parse_result.package_name, class),
+                            generated_class: generated_class_name(file),
+                            path: &file.path,
+                        })
+                        .collect(),
+                })
+                .collect();
+            let mut json = serde_json::to_vec_pretty(&JsonManifest { inputs })
+                .expect("the manifest is serializable as JSON");
+            json.push(b'\n');
+            json
+        }
+        ManifestFormat::Proto => {
+            let compilation_unit = inputs.iter()
+                .flat_map(|input| input.generated_files.iter().map(|file| CompilationUnit {
+                    path: Some(file.path.clone()),
+                    pkg: Some(input.parse_result.package_name.clone()),
+                    generated_by_annotation_processor: Some(true),
+                    top_level: vec![generated_class_name(file).rsplit('.').next().unwrap_or_default().to_string()],
+                }))
+                .collect();
+            Manifest { compilation_unit }.encode_to_vec()
+        }
+    }
+}
+
+/// Writes the manifest of the inputs (as for `encode`) to the output path.
+pub fn write_manifest(inputs: &[ManifestInput], format: ManifestFormat, output_path: &str) -> Result<()> {
+    fs::write(output_path, encode(inputs, format))
+        .map_err(|e| ManifestError::CannotWriteManifest(output_path.to_string(), e.to_string()))
+}
+
+/// The fully-qualified (canonical) name of a class, e.g. `com.example.Outer.Value`.
+fn qualified_class_name(package_name: &str, class: &ClassDeclarationState) -> String {
+    let mut segments = vec![];
+    if !package_name.is_empty() {
+        segments.push(package_name);
+    }
+    segments.extend(class.parent_chain.iter().rev().map(String::as_str));
+    segments.push(&class.name);
+    segments.join(".")
+}
+
+/// The fully-qualified name of the class in a generated file, which is always top-level.
+fn generated_class_name(file: &GeneratedFile) -> String {
+    file.path.trim_end_matches(".java").replace('/', ".")
+}
+
+
+#[cfg(test)]
+mod test {
+    use prost::Message;
+    use serde_json::{json, Value};
+    use crate::{generate, parse};
+    use super::{CompilationUnit, Manifest, ManifestFormat, ManifestInput};
+
+    const SOURCE: &str = r#"
+        package com.example;
+        import com.google.auto.value.AutoValue;
+        class Outer {
+            @AutoValue
+            abstract static class Value {
+                abstract String name();
+            }
+        }
+        "#;
+
+    #[test]
+    fn manifest_maps_inputs_to_generated_classes() {
+        let parse_result = parse::parse_source("Outer.java", SOURCE).unwrap();
+        let generated_files = generate::generate_sources(std::slice::from_ref(&parse_result));
+        let other = parse::parse_source("Other.java", "class Other {}").unwrap();
+        let hash = super::content_hash(SOURCE);
+        let inputs = [
+            ManifestInput { path: "Outer.java", sha256: &hash, parse_result: &parse_result, generated_files: &generated_files },
+            ManifestInput { path: "Other.java", sha256: "", parse_result: &other, generated_files: &[] },
+        ];
+
+        let manifest: Value = serde_json::from_slice(&super::encode(&inputs, ManifestFormat::Json)).unwrap();
+        assert_eq!(manifest["inputs"][0]["sha256"], hash);
+        assert_eq!(manifest["inputs"][0]["classes"], json!([{
+            "name": "com.example.Outer.Value",
+            "generated_class": "com.example.AutoValue_Outer_Value",
+            "path": "com/example/AutoValue_Outer_Value.java",
+        }]));
+        assert_eq!(manifest["inputs"][1]["path"], "Other.java");
+        assert_eq!(manifest["inputs"][1]["classes"], json!([]));
+
+        let manifest = Manifest::decode(super::encode(&inputs, ManifestFormat::Proto).as_slice()).unwrap();
+        assert_eq!(manifest.compilation_unit, vec![CompilationUnit {
+            path: Some("com/example/AutoValue_Outer_Value.java".to_string()),
+            pkg: Some("com.example".to_string()),
+            generated_by_annotation_processor: Some(true),
+            top_level: vec!["AutoValue_Outer_Value".to_string()],
+        }]);
+    }
+
+    #[test]
+    fn content_hash_is_sha256_hex() {
+        assert_eq!(super::content_hash(""), "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855");
+    }
+}
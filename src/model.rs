@@ -0,0 +1,262 @@
+//! A JSON dump of the AutoValue model that mavir parses from source files, for debugging and
+//! for tools such as schema or documentation generators.
+//!
+//! The document is an object with a `schema_version` (currently [`SCHEMA_VERSION`]) and one
+//! entry in `files` for every input, in input order. Each file has:
+//!
+//! - `path`: the source file (or `archive!/entry` for a source JAR entry)
+//! - `parsed`: false when the file was skipped without parsing, because its text can't
+//!   contain an AutoValue class. Skipped files have a null `package_name` and no `imports` or
+//!   `classes`.
+//! - `package_name`: empty for the default package
+//! - `imports`: each with a `name`, `is_static` and `is_wildcard`
+//! - `classes`: the `@AutoValue` classes in source order
+//!
+//! Each class has its `name`, the `parent_chain` of enclosing types (innermost first), its
+//! `modifiers`, a `span` and its `methods`. Each method (an abstract property method) has its
+//! `name`, `return_type` as written in Java, the structured `type`, whether it's `nullable`,
+//! its `modifiers` and a `span`.
+//!
+//! A `type` is an object with a `kind` and `annotations` (type annotations, such as
+//! `@Nullable` in `java.util.@Nullable List`), plus fields depending on the kind:
+//!
+//! - `primitive`: the `name` keyword, one of `boolean`, `byte`, `short`, `int`, `long`,
+//!   `char`, `float` or `double`
+//! - `class`: the `name`, the `package` if written fully-qualified (otherwise null), the
+//!   `outer_types` of a nested type written qualified (outermost first, each without a
+//!   `kind`) and its `type_arguments`
+//! - `array`: the `component` type; multi-dimensional arrays nest, outermost first
+//! - `type_variable`: the `name` of a type parameter
+//! - `wildcard`: the `bound`, null for `?` or an object with a `kind` of `extends` or `super`
+//!   and the bounding `type`
+//!
+//! Modifiers have an `access` level (`public`, `protected`, `package-private` or
+//! `private`), the other modifier keywords as `flags` (such as `abstract`, `static` or
+//! `non-sealed`), and `annotations` (each with a `name` as written, a resolved
+//! `qualified_name` or null, the source text of its `arguments` and a `span`). Spans have a
+//! `start_byte` and `end_byte` along with 1-based `start_line`, `start_column`, `end_line`
+//! and `end_column`, counting columns in bytes.
+//!
+//! The schema version is incremented whenever a field is removed or changes meaning. Fields
+//! may be added without changing it, so consumers should ignore fields they don't know.
+
+use std::fs;
+
+use serde::Serialize;
+use thiserror::Error;
+
+use crate::modifiers::{Modifiers, Span};
+use crate::parse::{ClassDeclarationState, ImportDeclaration, MethodDeclarationState, ParseResult};
+use crate::types::JavaType;
+
+/// The version of the model's schema.
+pub const SCHEMA_VERSION: u32 = 1;
+
+#[derive(Debug, Error)]
+pub enum ModelError {
+    #[error("Could not write model to {0}: {1}")]
+    CannotWriteModel(String, String),
+}
+
+pub type Result<T> = std::result::Result<T, ModelError>;
+
+#[derive(Serialize)]
+struct Model<'a> {
+    schema_version: u32,
+    files: Vec<FileModel<'a>>,
+}
+
+#[derive(Serialize)]
+struct FileModel<'a> {
+    path: &'a str,
+    parsed: bool,
+    package_name: Option<&'a str>,
+    imports: &'a [ImportDeclaration],
+    classes: Vec<ClassModel<'a>>,
+}
+
+#[derive(Serialize)]
+struct ClassModel<'a> {
+    name: &'a str,
+    parent_chain: &'a [String],
+    modifiers: &'a Modifiers,
+    span: Span,
+    methods: Vec<MethodModel<'a>>,
+}
+
+#[derive(Serialize)]
+struct MethodModel<'a> {
+    name: &'a str,
+    return_type: String,
+    #[serde(rename = "type")]
+    java_type: &'a JavaType,
+    nullable: bool,
+    modifiers: &'a Modifiers,
+    span: Span,
+}
+
+impl<'a> ClassModel<'a> {
+    fn new(class: &'a ClassDeclarationState) -> ClassModel<'a> {
+        ClassModel {
+            name: &class.name,
+            parent_chain: &class.parent_chain,
+            modifiers: &class.modifiers,
+            span: class.span,
+            methods: class.methods.iter().map(MethodModel::new).collect(),
+        }
+    }
+}
+
+impl<'a> MethodModel<'a> {
+    fn new(method: &'a MethodDeclarationState) -> MethodModel<'a> {
+        MethodModel {
+            name: &method.name,
+            return_type: method.return_type.to_string(),
+            java_type: &method.return_type,
+            nullable: method.is_nullable(),
+            modifiers: &method.modifiers,
+            span: method.span,
+        }
+    }
+}
+
+impl<'a> FileModel<'a> {
+    fn new(path: &'a str, parse_result: Option<&'a ParseResult>) -> FileModel<'a> {
+        match parse_result {
+            Some(parse_result) => FileModel {
+                path,
+                parsed: true,
+                package_name: Some(&parse_result.package_name),
+                imports: &parse_result.import_statements,
+                classes: parse_result.class_declarations.iter().map(ClassModel::new).collect(),
+            },
+            None => FileModel { path, parsed: false, package_name: None, imports: &[], classes: vec![] },
+        }
+    }
+}
+
+/// Serializes the model of every input, given as the path of the file with its parse result,
+/// or `None` if the file was skipped without being parsed.
+pub fn to_json(inputs: &[(&str, Option<&ParseResult>)]) -> String {
+    let files = inputs.iter()
+        .map(|(path, parse_result)| FileModel::new(path, *parse_result))
+        .collect();
+    let model = Model { schema_version: SCHEMA_VERSION, files };
+    let mut json = serde_json::to_string_pretty(&model).expect("the model is serializable as JSON");
+    json.push('\n');
+    json
+}
+
+/// Writes the model of the inputs (as for `to_json`) to the output path.
+pub fn write_model(inputs: &[(&str, Option<&ParseResult>)], output_path: &str) -> Result<()> {
+    fs::write(output_path, to_json(inputs))
+        .map_err(|e| ModelError::CannotWriteModel(output_path.to_string(), e.to_string()))
+}
+
+
+#[cfg(test)]
+mod test {
+    use serde_json::{json, Value};
+    use crate::parse;
+
+    #[test]
+    fn model_includes_types_nullability_and_spans() {
+        let source_code = r#"package com.example;
+import com.google.auto.value.AutoValue;
+import javax.annotation.Nullable;
+class Outer {
+  @AutoValue
+  public abstract static class Value {
+    @Nullable abstract java.util.List<String> names();
+  }
+}
+"#;
+        let parse_result = parse::parse_source("Value.java", source_code).unwrap();
+        let model: Value = serde_json::from_str(&super::to_json(&[("Value.java", Some(&parse_result))])).unwrap();
+
+        assert_eq!(model["schema_version"], super::SCHEMA_VERSION);
+        let files = model["files"].as_array().unwrap();
+        assert_eq!(files[0]["path"], "Value.java");
+        assert_eq!(files[0]["parsed"], true);
+        assert_eq!(files[0]["package_name"], "com.example");
+        assert_eq!(files[0]["imports"][1]["name"], "javax.annotation.Nullable");
+
+        let class = &files[0]["classes"][0];
+        assert_eq!(class["name"], "Value");
+        assert_eq!(class["parent_chain"], json!(["Outer"]));
+        assert_eq!(class["modifiers"]["access"], "public");
+        assert_eq!(class["modifiers"]["flags"], json!(["abstract", "static"]));
+        assert_eq!((&class["span"]["start_line"], &class["span"]["end_line"]), (&json!(5), &json!(8)));
+
+        let method = &class["methods"][0];
+        assert_eq!(method["name"], "names");
+        assert_eq!(method["return_type"], "java.util.List<String>");
+        assert_eq!(method["type"]["kind"], "class");
+        assert_eq!(method["type"]["package"], "java.util");
+        assert_eq!(method["type"]["type_arguments"][0]["name"], "String");
+        assert_eq!(method["nullable"], true);
+        assert_eq!(method["span"]["start_line"], 7);
+        assert_eq!(method["span"]["start_column"], 5);
+    }
+
+    #[test]
+    fn every_input_is_included() {
+        let without_classes = parse::parse_source("Other.java", "class Other {}").unwrap();
+        let model: Value = serde_json::from_str(
+            &super::to_json(&[("Other.java", Some(&without_classes)), ("Skipped.java", None)])).unwrap();
+
+        let files = model["files"].as_array().unwrap();
+        assert_eq!(files[0], json!({
+            "path": "Other.java",
+            "parsed": true,
+            "package_name": "",
+            "imports": [],
+            "classes": [],
+        }));
+        assert_eq!(files[1], json!({
+            "path": "Skipped.java",
+            "parsed": false,
+            "package_name": null,
+            "imports": [],
+            "classes": [],
+        }));
+    }
+
+    #[test]
+    fn types_are_tagged_by_kind() {
+        let source_code = r#"
+            import com.google.auto.value.AutoValue;
+            @AutoValue
+            abstract class Value<T> {
+                abstract int[] ints();
+                abstract T value();
+                abstract java.util.List<? super Number> numbers();
+            }
+            "#;
+        let parse_result = parse::parse_source("Value.java", source_code).unwrap();
+        let model: Value = serde_json::from_str(&super::to_json(&[("Value.java", Some(&parse_result))])).unwrap();
+
+        let methods = &model["files"][0]["classes"][0]["methods"];
+        assert_eq!(methods[0]["type"], json!({
+            "kind": "array",
+            "component": {"kind": "primitive", "name": "int", "annotations": []},
+            "annotations": [],
+        }));
+        assert_eq!(methods[1]["type"], json!({"kind": "type_variable", "name": "T", "annotations": []}));
+        assert_eq!(methods[2]["type"]["type_arguments"][0], json!({
+            "kind": "wildcard",
+            "bound": {
+                "kind": "super",
+                "type": {
+                    "kind": "class",
+                    "package": null,
+                    "outer_types": [],
+                    "name": "Number",
+                    "type_arguments": [],
+                    "annotations": [],
+                },
+            },
+            "annotations": [],
+        }));
+    }
+}
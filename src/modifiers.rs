@@ -0,0 +1,267 @@
+use std::collections::BTreeSet;
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+use tree_sitter::Node;
+
+use crate::parse::qualified_name;
+use crate::resolve::{self, TypeScope};
+
+/// The modifiers of a class or method declaration, built from the (modifiers) node.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Modifiers {
+    pub access: AccessLevel,
+    /// The non-access modifier keywords, such as `abstract` or `static`
+    pub flags: BTreeSet<Modifier>,
+    pub annotations: Vec<Annotation>,
+}
+
+/// The access level given by a declaration's modifiers. Declarations without an access
+/// modifier are package-private. Serialized in kebab-case, e.g. `public` or `package-private`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum AccessLevel {
+    Public,
+    Protected,
+    #[default]
+    PackagePrivate,
+    Private,
+}
+
+/// Non-access modifier keywords, serialized as the keyword (e.g. `static` or `non-sealed`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Modifier {
+    Abstract,
+    Default,
+    Final,
+    Native,
+    NonSealed,
+    Sealed,
+    Static,
+    Strictfp,
+    Synchronized,
+    Transient,
+    Volatile,
+}
+
+/// An annotation on a declaration or type.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Annotation {
+    /// The annotation name as written in the source, e.g. `AutoValue.Builder`
+    pub name: String,
+    /// The fully-qualified name of the annotation, if it could be resolved
+    pub qualified_name: Option<String>,
+    /// The source text of each argument, e.g. `"first name"` or `value = 1`
+    pub arguments: Vec<String>,
+    pub span: Span,
+}
+
+/// A range in the source file. Lines and columns are 1-based.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Span {
+    pub start_byte: usize,
+    pub end_byte: usize,
+    pub start_line: usize,
+    pub start_column: usize,
+    pub end_line: usize,
+    pub end_column: usize,
+}
+
+impl Modifiers {
+    /// Collects the modifiers of a declaration node (such as a class_declaration or
+    /// method_declaration). Declarations without modifiers get the defaults.
+    pub(crate) fn from_declaration(node: Node, source_code: &str, scope: &TypeScope) -> Modifiers {
+        let mut cursor = node.walk();
+        let modifiers_node = node.children(&mut cursor).find(|n| n.kind() == "modifiers");
+        match modifiers_node {
+            Some(modifiers_node) => Modifiers::from_node(modifiers_node, source_code, scope),
+            None => Modifiers::default(),
+        }
+    }
+
+    /// Builds the modifiers from a (modifiers) node. Comments within the modifiers are skipped.
+    pub(crate) fn from_node(node: Node, source_code: &str, scope: &TypeScope) -> Modifiers {
+        let mut modifiers = Modifiers::default();
+        let mut cursor = node.walk();
+        for child in node.children(&mut cursor) {
+            match child.kind() {
+                "public" => modifiers.access = AccessLevel::Public,
+                "protected" => modifiers.access = AccessLevel::Protected,
+                "private" => modifiers.access = AccessLevel::Private,
+                "marker_annotation" | "annotation" => {
+                    if let Some(annotation) = Annotation::from_node(child, source_code, scope) {
+                        modifiers.annotations.push(annotation);
+                    }
+                }
+                keyword => {
+                    if let Some(flag) = Modifier::from_keyword(keyword) {
+                        modifiers.flags.insert(flag);
+                    }
+                }
+            }
+        }
+        modifiers
+    }
+
+    pub fn has(&self, modifier: Modifier) -> bool {
+        self.flags.contains(&modifier)
+    }
+
+    /// Determines if one of the annotations resolves to the given fully-qualified name.
+    pub fn is_annotated(&self, qualified_name: &str) -> bool {
+        self.annotations
+            .iter()
+            .any(|a| a.qualified_name.as_deref() == Some(qualified_name))
+    }
+
+    /// Returns the `@Nullable` annotation, if present.
+    pub fn nullable_annotation(&self) -> Option<&Annotation> {
+        self.annotations
+            .iter()
+            .find(|a| resolve::is_nullable_annotation(&a.name))
+    }
+}
+
+impl AccessLevel {
+    /// The keyword for the access level, or `None` for package-private access.
+    pub fn keyword(&self) -> Option<&'static str> {
+        match self {
+            AccessLevel::Public => Some("public"),
+            AccessLevel::Protected => Some("protected"),
+            AccessLevel::PackagePrivate => None,
+            AccessLevel::Private => Some("private"),
+        }
+    }
+}
+
+impl Modifier {
+    fn from_keyword(keyword: &str) -> Option<Modifier> {
+        let modifier = match keyword {
+            "abstract" => Modifier::Abstract,
+            "default" => Modifier::Default,
+            "final" => Modifier::Final,
+            "native" => Modifier::Native,
+            "non-sealed" => Modifier::NonSealed,
+            "sealed" => Modifier::Sealed,
+            "static" => Modifier::Static,
+            "strictfp" => Modifier::Strictfp,
+            "synchronized" => Modifier::Synchronized,
+            "transient" => Modifier::Transient,
+            "volatile" => Modifier::Volatile,
+            _ => return None,
+        };
+        Some(modifier)
+    }
+}
+
+impl Annotation {
+    /// Builds an annotation from a (marker_annotation) or (annotation) node.
+    pub(crate) fn from_node(node: Node, source_code: &str, scope: &TypeScope) -> Option<Annotation> {
+        let name = qualified_name(node.child_by_field_name("name")?, source_code);
+        let arguments = match node.child_by_field_name("arguments") {
+            Some(arguments_node) => {
+                let mut cursor = arguments_node.walk();
+                let arguments = arguments_node.named_children(&mut cursor)
+                    .filter(|n| !n.is_extra())
+                    .map(|n| source_code[n.start_byte()..n.end_byte()].to_string())
+                    .collect();
+                arguments
+            }
+            None => vec![],
+        };
+        Some(Annotation {
+            qualified_name: scope.resolve_annotation(&name),
+            name,
+            arguments,
+            span: Span::from_node(node),
+        })
+    }
+}
+
+impl fmt::Display for Annotation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "@{}", self.name)?;
+        if !self.arguments.is_empty() {
+            write!(f, "({})", self.arguments.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+impl Span {
+    pub(crate) fn from_node(node: Node) -> Span {
+        let start = node.start_position();
+        let end = node.end_position();
+        Span {
+            start_byte: node.start_byte(),
+            end_byte: node.end_byte(),
+            start_line: start.row + 1,
+            start_column: start.column + 1,
+            end_line: end.row + 1,
+            end_column: end.column + 1,
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use tree_sitter::Parser;
+    use crate::resolve::{TypeScope, AUTO_VALUE};
+    use super::{AccessLevel, Modifier, Modifiers};
+
+    fn method_modifiers(source_code: &str) -> Modifiers {
+        let mut parser = Parser::new();
+        parser.set_language(&tree_sitter_java::language()).unwrap();
+        let tree = parser.parse(source_code, None).unwrap();
+
+        // program -> class_declaration -> class_body -> method_declaration
+        let class_node = tree.root_node().named_child(0).unwrap();
+        let body = class_node.child_by_field_name("body").unwrap();
+        let method = body.named_child(0).unwrap();
+        let scope = TypeScope::new("", &[], Default::default());
+        Modifiers::from_declaration(method, source_code, &scope)
+    }
+
+    #[test]
+    fn annotations_with_arguments_newlines_and_comments() {
+        let modifiers = method_modifiers(r#"
+            abstract class TestClass {
+                @JsonProperty("first name")
+                @Nullable /* nullable */
+                public
+                // abstract accessor
+                abstract String name();
+            }
+            "#);
+        assert_eq!(modifiers.access, AccessLevel::Public);
+        assert_eq!(modifiers.flags.iter().copied().collect::<Vec<_>>(), vec![Modifier::Abstract]);
+
+        let annotations = modifiers.annotations
+            .iter()
+            .map(|a| a.to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(annotations, vec![r#"@JsonProperty("first name")"#, "@Nullable"]);
+        assert_eq!(modifiers.nullable_annotation().unwrap().span.start_line, 4);
+    }
+
+    #[test]
+    fn annotation_arguments_and_qualified_names() {
+        let modifiers = method_modifiers(r#"
+            abstract class TestClass {
+                @com.google.auto.value.AutoValue
+                @SuppressWarnings(value = {"a", "b"})
+                static abstract String name();
+            }
+            "#);
+        assert_eq!(modifiers.access, AccessLevel::PackagePrivate);
+        assert!(modifiers.has(Modifier::Static));
+        assert!(modifiers.has(Modifier::Abstract));
+        assert!(modifiers.is_annotated(AUTO_VALUE));
+        assert_eq!(modifiers.annotations[1].arguments, vec![r#"value = {"a", "b"}"#]);
+        assert_eq!(
+            modifiers.annotations[1].qualified_name.as_deref(),
+            Some("java.lang.SuppressWarnings"));
+    }
+}
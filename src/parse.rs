@@ -1,17 +1,57 @@
 use derive_builder::Builder;
-use tree_sitter::{Node, Parser, Query, QueryCursor};
+use serde::{Deserialize, Serialize};
+use tree_sitter::{Node, Parser, Point, Query, QueryCursor, Tree};
 use thiserror::Error;
 
+use std::cell::RefCell;
+use std::collections::HashSet;
+use std::fmt;
 use std::fs;
-use log::debug;
+use std::sync::OnceLock;
+use log::{debug, warn};
 
-#[derive(Debug)]
+use crate::modifiers::{Annotation, Modifier, Modifiers, Span};
+use crate::resolve::{self, TypeScope};
+use crate::types::JavaType;
+
+/// The AutoValue classes found in a source file, along with the context needed to generate
+/// code for them.
+#[derive(Debug, Default, Serialize, Deserialize)]
 pub struct ParseResult {
+    /// The package of the source file, or an empty name for the default package
     pub package_name: String,
-    pub import_statements: Vec<String>,
+    pub import_statements: Vec<ImportDeclaration>,
+    /// The classes annotated with `@AutoValue`, at any level of nesting, in source order
     pub class_declarations: Vec<ClassDeclarationState>,
 }
 
+/// A single import statement from the source file. For wildcard imports the name is the
+/// package (or type) being imported from, without the trailing `.*`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ImportDeclaration {
+    pub name: String,
+    pub is_static: bool,
+    pub is_wildcard: bool,
+}
+
+impl ImportDeclaration {
+    /// The simple name brought into scope by this import, or `None` for wildcard imports.
+    pub fn simple_name(&self) -> Option<&str> {
+        if self.is_wildcard {
+            return None;
+        }
+        self.name.rsplit('.').next()
+    }
+}
+
+impl fmt::Display for ImportDeclaration {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let static_kw = if self.is_static { "static " } else { "" };
+        let wildcard = if self.is_wildcard { ".*" } else { "" };
+        write!(f, "import {}{}{};", static_kw, self.name, wildcard)
+    }
+}
+
 #[derive(Debug, Error)]
 pub enum ParseError {
     #[error("Could not initialize parser for Java code (internal tool error)")]
@@ -21,116 +61,272 @@ pub enum ParseError {
     #[error("File is not parsable as Java code")]
     FileNotParsableAsJava,
     #[error("File processing error: {0}")]
-    FileProcessingError(String)
+    FileProcessingError(String),
+    #[error("Syntax error at {0}")]
+    SyntaxError(String),
+}
+
+/// A problem with an AutoValue class that doesn't prevent generating code for it, such as a
+/// feature that mavir doesn't support. Positions are zero-based, with byte columns.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub start: Point,
+    pub end: Point,
+    pub message: String,
 }
 
 pub type Result<T> = std::result::Result<T, ParseError>;
 
-pub fn parse_file(file_path: &str) -> Result<ParseResult> {
-    let mut parser = Parser::new();
-    parser.set_language(&tree_sitter_java::language())
-        .map_err(|_| ParseError::ParserInitializationError)?;
+/// Reads a Java source file, for parsing with `parse_source`.
+pub fn read_file(file_path: &str) -> Result<String> {
+    fs::read_to_string(file_path)
+        .map_err(|_| ParseError::CannotReadFile(file_path.to_string()))
+}
 
-    println!("Parsing file: {}", file_path);
+/// Parses Java source code that was read from `file_path`. The path is only used to identify
+/// the source in output and diagnostics, and need not exist on disk (e.g. an entry within a
+/// source JAR).
+pub fn parse_source(file_path: &str, source_code: &str) -> Result<ParseResult> {
+    debug!("Parsing file: {}", file_path);
+    let tree = parse_tree(source_code, None)?;
 
-    let source_code = fs::read_to_string(file_path)
-        .map_err(|_| ParseError::CannotReadFile(file_path.to_string()))?;
-    let tree = parser.parse(&source_code, None).ok_or(ParseError::FileNotParsableAsJava)?;
+    // Standard output is reserved for the worker protocol, so the tree is only logged
+    debug!("File parsed successfully: {}", tree.root_node().to_sexp());
 
-    println!("File parsed successfully");
-    let root_node = tree.root_node();
-    println!("{}", root_node.to_sexp());
-    println!("---------");
+    let (parse_result, diagnostics) = collect_parse_result(&tree, source_code)?;
+    for diagnostic in diagnostics {
+        warn!("{}:{}: {}", file_path, diagnostic.start.row + 1, diagnostic.message);
+    }
+    Ok(parse_result)
+}
 
-    let package_name = collect_package(&tree, &source_code)?;
-    println!("Package name: {}", package_name);
-    let import_statements = collect_import_statements(&tree, &source_code);
-    println!("---------");
-    let class_declarations = collect_classes(&tree, &source_code)?;
-    println!("---------");
+/// A cheap pre-filter for callers that process many files: a file that never mentions
+/// AutoValue can't contain an AutoValue class, so there is no need to parse it.
+pub fn may_contain_autovalue(source_code: &str) -> bool {
+    source_code.contains("AutoValue")
+}
 
-    Ok(ParseResult {
+/// Parses source code into a syntax tree. When the source is an edited version of a document
+/// that was parsed before, passing the old tree (updated with `Tree::edit`) lets the parser
+/// reuse the parts of it that didn't change.
+pub fn parse_tree(source_code: &str, old_tree: Option<&Tree>) -> Result<Tree> {
+    with_parser(|parser| parser.parse(source_code, old_tree))?
+        .ok_or(ParseError::FileNotParsableAsJava)
+}
+
+/// Collects the AutoValue classes (and everything needed to generate code for them) from a
+/// parsed tree, along with diagnostics for the features they use that mavir doesn't support.
+pub fn collect_parse_result(tree: &Tree, source_code: &str) -> Result<(ParseResult, Vec<Diagnostic>)> {
+    let package_name = collect_package(tree, source_code)?;
+    debug!("Package name: {}", package_name);
+    let import_statements = collect_import_statements(tree, source_code);
+    let scope = TypeScope::new(
+        &package_name,
+        &import_statements,
+        collect_declared_types(tree, source_code));
+    let mut diagnostics = vec![];
+    let class_declarations = collect_classes(tree, source_code, &scope, &mut diagnostics)?;
+
+    Ok((ParseResult {
         package_name,
         import_statements,
         class_declarations,
+    }, diagnostics))
+}
+
+thread_local! {
+    /// Each thread reuses its own parser, since files may be parsed in parallel
+    static PARSER: RefCell<Option<Parser>> = const { RefCell::new(None) };
+}
+
+/// Runs the function with this thread's parser, creating the parser on first use.
+fn with_parser<T>(f: impl FnOnce(&mut Parser) -> T) -> Result<T> {
+    PARSER.with(|parser| {
+        let mut parser = parser.borrow_mut();
+        if parser.is_none() {
+            let mut new_parser = Parser::new();
+            new_parser.set_language(&tree_sitter_java::language())
+                .map_err(|_| ParseError::ParserInitializationError)?;
+            *parser = Some(new_parser);
+        }
+        Ok(f(parser.as_mut().unwrap()))
     })
+}
+
+/// Checks that the source code parses without syntax errors, reporting the position of the
+/// first error. The parser recovers from errors, so `parse_source` still produces a (partial)
+/// result for such files.
+pub fn check_syntax(file_path: &str, source_code: &str) -> Result<()> {
+    check_tree_syntax(file_path, &parse_tree(source_code, None)?)
+}
 
+/// Checks that an already parsed tree has no syntax errors, as for `check_syntax`.
+pub fn check_tree_syntax(file_path: &str, tree: &Tree) -> Result<()> {
+    match syntax_error(tree) {
+        None => Ok(()),
+        Some(error) => Err(ParseError::SyntaxError(
+            format!("{}:{}:{}", file_path, error.start.row + 1, error.start.column + 1))),
+    }
+}
+
+/// Returns a diagnostic for the first syntax error in the tree, if there is one.
+pub fn syntax_error(tree: &Tree) -> Option<Diagnostic> {
+    let mut node = tree.root_node();
+    if !node.has_error() {
+        return None;
+    }
+    // Descend into the first child containing an error, until reaching the error itself
+    'descend:
+    while !node.is_error() && !node.is_missing() {
+        let mut cursor = node.walk();
+        for child in node.children(&mut cursor) {
+            if child.has_error() {
+                node = child;
+                continue 'descend;
+            }
+        }
+        break;
+    }
+    let message = if node.is_missing() {
+        format!("Syntax error: missing {}", node.kind())
+    } else {
+        "Syntax error".to_string()
+    };
+    Some(Diagnostic { start: node.start_position(), end: node.end_position(), message })
 }
 
+/// Returns the query, compiling it on first use. Compiled queries are shared between threads.
+fn compiled_query(cell: &'static OnceLock<Query>, source: &str) -> &'static Query {
+    cell.get_or_init(|| Query::new(&tree_sitter_java::language(), source)
+        .expect("mavir's queries are valid for the Java grammar"))
+}
 
 /// Runs a simple query on the tree to find the package declaration and return the
-/// package name. Returns an error if there are issues parsing, but this is not
-/// expected to error when processing a valid Java file.
+/// package name. Files without a package declaration are in the default package, which
+/// is represented by an empty name.
 fn collect_package(tree: &tree_sitter::Tree, source_code: &str) -> Result<String> {
-    // Query to find package declaration
-    let query = Query::new(&tree_sitter_java::language(), r#"
-      (package_declaration) @package
-    "#).unwrap();
+    // Query to find the name within the package declaration. Matching on the name node (rather
+    // than the declaration text) skips over package annotations, comments and whitespace.
+    static QUERY: OnceLock<Query> = OnceLock::new();
+    let query = compiled_query(&QUERY, r#"
+      (package_declaration [(identifier) (scoped_identifier)] @package-name)
+    "#);
     let mut cursor = QueryCursor::new();
-    let matches = cursor.matches(&query, tree.root_node(), source_code.as_bytes());
-    for m in matches {
-        for capture in m.captures {
-            let node = capture.node;
-            let package_text = &source_code[node.start_byte()..node.end_byte()];
-
-            // Extract the package text by removing the prefix "package " and postfix ";"
-            let package_name = package_text
-                .strip_prefix("package ")
-                .and_then(|p| p.strip_suffix(";"))
-                .ok_or(ParseError::FileProcessingError("Malformed package declaration".to_string()))?
-                .to_string();
-            return Ok(package_name);
-        }
+    let mut matches = cursor.matches(query, tree.root_node(), source_code.as_bytes());
+    match matches.next().and_then(|m| m.captures.first().map(|c| c.node)) {
+        Some(node) => Ok(qualified_name(node, source_code)),
+        None => Ok(String::new()),
     }
-    Err(ParseError::FileProcessingError("Could not find package declaration".to_string()))
 }
 
-/// Runs a simple query to collect the full text of all the import statements in the
-/// Java file. Returns this a vector of strings with each entry being a single import.
-fn collect_import_statements(tree: &tree_sitter::Tree, source_code: &str) -> Vec<String> {
-    let query = Query::new(&tree_sitter_java::language(), r#"
+/// Runs a simple query to collect all the import statements in the Java file. Returns a
+/// vector with each entry being a single import.
+fn collect_import_statements(tree: &tree_sitter::Tree, source_code: &str) -> Vec<ImportDeclaration> {
+    static QUERY: OnceLock<Query> = OnceLock::new();
+    let query = compiled_query(&QUERY, r#"
       (import_declaration) @import
-    "#).unwrap();
+    "#);
     let mut cursor = QueryCursor::new();
-    let matches = cursor.matches(&query, tree.root_node(), source_code.as_bytes());
+    let matches = cursor.matches(query, tree.root_node(), source_code.as_bytes());
     let mut import_statements = Vec::new();
     for m in matches {
         for capture in m.captures {
             let node = capture.node;
-            let import_text = &source_code[node.start_byte()..node.end_byte()];
-            import_statements.push(import_text.to_string());
+            let mut import = ImportDeclaration {
+                name: String::new(),
+                is_static: false,
+                is_wildcard: false,
+            };
+            let mut child_cursor = node.walk();
+            for child in node.children(&mut child_cursor) {
+                match child.kind() {
+                    "static" => import.is_static = true,
+                    "asterisk" => import.is_wildcard = true,
+                    "identifier" | "scoped_identifier" => {
+                        import.name = qualified_name(child, source_code);
+                    }
+                    _ => {}
+                }
+            }
+            import_statements.push(import);
         }
     }
     import_statements
 }
 
-#[derive(Debug, Builder, Default)]
+/// Returns the dotted name of an (identifier) or (scoped_identifier) node, skipping any
+/// whitespace or comments that appear between the segments.
+pub(crate) fn qualified_name(node: Node, source_code: &str) -> String {
+    if node.kind() != "scoped_identifier" {
+        return source_code[node.start_byte()..node.end_byte()].to_string();
+    }
+    let scope = node.child_by_field_name("scope")
+        .map(|n| qualified_name(n, source_code));
+    let name = node.child_by_field_name("name")
+        .map(|n| &source_code[n.start_byte()..n.end_byte()])
+        .unwrap_or_default();
+    match scope {
+        Some(scope) => format!("{}.{}", scope, name),
+        None => name.to_string(),
+    }
+}
+
+/// An `@AutoValue` class declaration.
+#[derive(Debug, Builder, Default, Serialize, Deserialize)]
 pub struct ClassDeclarationState {
+    /// The simple name of the class
     pub name: String,
+    /// The abstract methods declared by the class, which become its properties
     pub methods: Vec<MethodDeclarationState>,
+    /// The names of the enclosing type declarations of a nested class, innermost first
     pub parent_chain: Vec<String>,
-    pub modifiers: Vec<String>,
+    pub modifiers: Modifiers,
+    /// The location of the class declaration, including its annotations
+    pub span: Span,
 }
 
-#[derive(Debug, Builder, Default, Clone)]
+/// An abstract method of an `@AutoValue` class, i.e. a property of the generated class.
+#[derive(Debug, Builder, Clone, Serialize, Deserialize)]
 pub struct MethodDeclarationState {
     pub name: String,
-    pub return_type: String,
-    pub modifiers: Vec<String>,
-    #[builder(default)]
-    pub is_nullable: bool,
+    pub return_type: JavaType,
+    /// The method's modifiers, including its annotations
+    pub modifiers: Modifiers,
+    /// The location of the method declaration, including its annotations
+    pub span: Span,
+}
+
+impl MethodDeclarationState {
+    /// Returns the `@Nullable` annotation on the method, or on its return type.
+    pub fn nullable_annotation(&self) -> Option<&Annotation> {
+        self.modifiers.nullable_annotation().or_else(|| {
+            self.return_type.annotations()
+                .iter()
+                .find(|a| resolve::is_nullable_annotation(&a.name))
+        })
+    }
+
+    pub fn is_nullable(&self) -> bool {
+        self.nullable_annotation().is_some()
+    }
 }
 
-fn collect_classes(tree: &tree_sitter::Tree, source_code: &str) -> Result<Vec<ClassDeclarationState>> {
+fn collect_classes(
+    tree: &tree_sitter::Tree,
+    source_code: &str,
+    scope: &TypeScope,
+    diagnostics: &mut Vec<Diagnostic>,
+) -> Result<Vec<ClassDeclarationState>> {
     // Query to find classes
-    let query = Query::new(&tree_sitter_java::language(), r#"
+    static QUERY: OnceLock<Query> = OnceLock::new();
+    let query = compiled_query(&QUERY, r#"
       (class_declaration
         (modifiers) @modifiers
         name: (identifier) @class-name
       )
-    "#).unwrap();
+    "#);
     let mut cursor = QueryCursor::new();
-    let matches = cursor.matches(&query, tree.root_node(), source_code.as_bytes());
+    let matches = cursor.matches(query, tree.root_node(), source_code.as_bytes());
 
     let mut class_states: Vec<ClassDeclarationState> = vec![];
 
@@ -149,30 +345,26 @@ fn collect_classes(tree: &tree_sitter::Tree, source_code: &str) -> Result<Vec<Cl
                     // AutoValue (annotated) class. If it's not, then bail out of additional
                     // processing.
                     let parent_node = node.parent().unwrap();
-                    let av_class = has_autovalue_annotation(parent_node, source_code, class_name);
+                    let av_class = has_autovalue_annotation(parent_node, source_code, scope);
                     if !av_class {
                         // If this isn't an AutoValue class, no need to continue processing, move
                         // on to the next match.
                         continue 'query_match;
                     }
 
+                    diagnostics.extend(unsupported_features(parent_node, source_code, scope));
+
                     // Collect abstract methods
-                    state.methods(collect_abstract_method(parent_node, source_code, class_name)?);
+                    state.methods(collect_abstract_method(parent_node, source_code, scope)?);
 
                     // Find the class's parent class(es) (if any)
                     state.parent_chain(collect_parent_chain(parent_node, source_code));
+                    state.span(Span::from_node(parent_node));
                 }
                 "modifiers" => {
-                    // Collect the modifiers for the class that we need to proxy to the generated
-                    // class. Right now the only modifier we proxy (that makes sense) is the
-                    // "public" access modifier.
-                    // Modifiers also include items like "static" or annotations
-                    let text = &source_code[node.start_byte()..node.end_byte()];
-                    let modifiers = text.split(" ")
-                        .filter(|m| *m == "public")
-                        .map(str::to_string)
-                        .collect::<Vec<String>>();
-                    state.modifiers(modifiers);
+                    // Collect the modifiers for the class, some of which are proxied to the
+                    // generated class.
+                    state.modifiers(Modifiers::from_node(node, source_code, scope));
                 }
                 _ => {}
             }
@@ -188,69 +380,93 @@ fn collect_classes(tree: &tree_sitter::Tree, source_code: &str) -> Result<Vec<Cl
     Ok(class_states)
 }
 
-/// Determines if a given class (specified by the node and class-name) is annotated with
-/// an '@AutoValue' annotation.
-fn has_autovalue_annotation(node: Node, source_code: &str, class_name: &str) -> bool {
-    // Construct a query, using predicates to select match the exact class and annotation name
-    let query = Query::new(&tree_sitter_java::language(), &format!(r#"
-      (class_declaration
-        (modifiers
-            ((marker_annotation) @marker_annotation
-              (#eq? @marker_annotation "@AutoValue"))
-        )
-        name: ((identifier) @class-name (#eq? @class-name "{}"))
-      )
-    "#, class_name)).unwrap();
-    let mut cursor = QueryCursor::new();
-    let matches = cursor.matches(&query, node, source_code.as_bytes());
+/// Determines if a given class (specified by its class_declaration node) is annotated with
+/// `@com.google.auto.value.AutoValue`, whether imported, wildcard-imported or fully-qualified.
+fn has_autovalue_annotation(node: Node, source_code: &str, scope: &TypeScope) -> bool {
+    Modifiers::from_declaration(node, source_code, scope).is_annotated(resolve::AUTO_VALUE)
+}
 
-    // If we have a match, then we found the AutoValue annotation on the desired class. No
-    // need to process the match results.
-    matches.count() > 0
+/// Reports the AutoValue features used by the class that mavir doesn't generate code for. The
+/// generated class is still emitted, but won't compile against the source.
+fn unsupported_features(node: Node, source_code: &str, scope: &TypeScope) -> Vec<Diagnostic> {
+    let Some(body) = node.child_by_field_name("body") else {
+        return vec![];
+    };
+    let mut diagnostics = vec![];
+    let mut cursor = body.walk();
+    for member in body.named_children(&mut cursor) {
+        let modifiers = Modifiers::from_declaration(member, source_code, scope);
+        let message = if member.kind() == "class_declaration" && modifiers.is_annotated(resolve::AUTO_VALUE_BUILDER) {
+            "@AutoValue.Builder is not supported, no builder will be generated"
+        } else if member.kind() == "method_declaration" && modifiers.is_annotated(resolve::MEMOIZED) {
+            "@Memoized is not supported, the method will not be memoized"
+        } else {
+            continue;
+        };
+        // Point at the member's name rather than the whole (possibly long) declaration
+        let name = member.child_by_field_name("name").unwrap_or(member);
+        diagnostics.push(Diagnostic {
+            start: name.start_position(),
+            end: name.end_position(),
+            message: message.to_string(),
+        });
+    }
+    diagnostics
 }
 
-/// Builds up a MethodDeclarationState from the given (method_declaration) node.
-fn collect_abstract_method(node: Node, source_code: &str, class_name: &str) -> Result<Vec<MethodDeclarationState>> {
-    let query = Query::new(&tree_sitter_java::language(), &format!(r#"
-      (class_declaration
-        name: (identifier) @class-name (#eq? @class-name "{}")
-        body: (class_body
-          (method_declaration
-            (modifiers) @modifiers
-            type: _ @return-type
-            name: (identifier) @method-name
-            parameters: (formal_parameters)
-          )
-        )
-      )
-    "#, class_name)).unwrap();
+/// Builds up a MethodDeclarationState for each abstract method declared directly in the body
+/// of the given (class_declaration) node.
+fn collect_abstract_method(
+    node: Node,
+    source_code: &str,
+    scope: &TypeScope,
+) -> Result<Vec<MethodDeclarationState>> {
+    let Some(body) = node.child_by_field_name("body") else {
+        return Ok(vec![]);
+    };
+    static QUERY: OnceLock<Query> = OnceLock::new();
+    let query = compiled_query(&QUERY, r#"
+      (method_declaration
+        (modifiers) @modifiers
+        type: _ @return-type
+        name: (identifier) @method-name
+        parameters: (formal_parameters)
+      ) @method
+    "#);
     let mut cursor = QueryCursor::new();
-    let matches = cursor.matches(&query, node, source_code.as_bytes());
+    let matches = cursor.matches(query, body, source_code.as_bytes());
+    let type_parameters = collect_type_parameters(node, source_code);
 
     let mut methods: Vec<MethodDeclarationState> = vec![];
 
     'query_match:
     for m in matches {
+        // Skip the methods of nested classes, which are matched within the body as well
+        let is_member = m.captures.iter()
+            .any(|c| c.node.kind() == "method_declaration" && c.node.parent() == Some(body));
+        if !is_member {
+            continue;
+        }
+
         let mut state = MethodDeclarationStateBuilder::default();
         for capture in m.captures {
             let node = capture.node;
             let text = &source_code[node.start_byte()..node.end_byte()];
             match query.capture_names()[capture.index as usize] {
                 "return-type" => {
-                    state.return_type(text.to_string());
+                    state.return_type(JavaType::from_node(node, source_code, scope, &type_parameters)?);
                 }
                 "method-name" => {
                     state.name(text.to_string());
                 }
+                "method" => {
+                    state.span(Span::from_node(node));
+                }
                 "modifiers" => {
-                    let modifiers: Vec<String> = text.split(" ")
-                        .map(|m| m.trim().to_string())
-                        .filter(|m| m != "")
-                        .collect();
-                    if modifiers.iter().find(|m| *m == "abstract").is_none() {
+                    let modifiers = Modifiers::from_node(node, source_code, scope);
+                    if !modifiers.has(Modifier::Abstract) {
                         continue 'query_match;
                     }
-                    state.is_nullable(modifiers.iter().find(|m| *m == "@Nullable").is_some());
                     state.modifiers(modifiers);
                 }
                 _ => {}
@@ -264,7 +480,54 @@ fn collect_abstract_method(node: Node, source_code: &str, class_name: &str) -> R
     Ok(methods)
 }
 
-/// Given a node to a class, return the chain of parent classes
+/// Collects the names of the type parameters declared by a class_declaration node.
+fn collect_type_parameters(node: Node, source_code: &str) -> HashSet<String> {
+    let Some(type_parameters) = node.child_by_field_name("type_parameters") else {
+        return HashSet::new();
+    };
+    let mut cursor = type_parameters.walk();
+    let names = type_parameters.named_children(&mut cursor)
+        .filter(|n| n.kind() == "type_parameter")
+        .filter_map(|n| {
+            let mut param_cursor = n.walk();
+            let name = n.named_children(&mut param_cursor)
+                .find(|c| c.kind() == "type_identifier")?;
+            Some(source_code[name.start_byte()..name.end_byte()].to_string())
+        })
+        .collect();
+    names
+}
+
+/// Collects the simple names of every type declared in the file, at any level of nesting.
+fn collect_declared_types(tree: &tree_sitter::Tree, source_code: &str) -> HashSet<String> {
+    static QUERY: OnceLock<Query> = OnceLock::new();
+    let query = compiled_query(&QUERY, r#"
+      [
+        (class_declaration name: (identifier) @type-name)
+        (interface_declaration name: (identifier) @type-name)
+        (enum_declaration name: (identifier) @type-name)
+        (record_declaration name: (identifier) @type-name)
+        (annotation_type_declaration name: (identifier) @type-name)
+      ]
+    "#);
+    let mut cursor = QueryCursor::new();
+    cursor.matches(query, tree.root_node(), source_code.as_bytes())
+        .flat_map(|m| m.captures.iter().map(|c| c.node))
+        .map(|node| source_code[node.start_byte()..node.end_byte()].to_string())
+        .collect()
+}
+
+/// Node kinds that declare a type, and so contribute a segment to a nested class's name.
+const TYPE_DECLARATION_KINDS: [&str; 5] = [
+    "class_declaration",
+    "interface_declaration",
+    "enum_declaration",
+    "record_declaration",
+    "annotation_type_declaration",
+];
+
+/// Given a node to a class, return the chain of enclosing type declarations (classes,
+/// interfaces, enums, records and annotation types), innermost first.
 fn collect_parent_chain(node: Node, source_code: &str) -> Vec<String> {
     let mut chain: Vec<String> = vec![];
     if node.parent().is_none() {
@@ -274,16 +537,16 @@ fn collect_parent_chain(node: Node, source_code: &str) -> Vec<String> {
     let mut current_node = node;
     while current_node.parent().is_some() {
         current_node = current_node.parent().unwrap();
-        if current_node.kind() != "class_declaration" {
+        if !TYPE_DECLARATION_KINDS.contains(&current_node.kind()) {
             continue;
         }
 
-        // current node is a class_declaration, get the class-name
+        // current node is a type declaration, get the type-name
         let name = current_node
             .child_by_field_name("name")
             .map(|n| source_code[n.start_byte()..n.end_byte()].to_string());
-        if name.is_some() {
-            chain.push(name.unwrap());
+        if let Some(name) = name {
+            chain.push(name);
         }
     }
     chain
@@ -292,94 +555,284 @@ fn collect_parent_chain(node: Node, source_code: &str) -> Vec<String> {
 
 #[cfg(test)]
 mod test {
+    use crate::resolve::TypeScope;
+
     #[test]
-    fn find_annotation_when_only_one_present() {
+    fn collects_only_the_class_own_abstract_methods() {
+        let result = super::parse_source("Outer.java", r#"
+            import com.google.auto.value.AutoValue;
+            @AutoValue
+            abstract class Value$Ünïcode {
+                abstract int first();
+                int notAbstract() { return 1; }
+                @AutoValue
+                abstract static class Nested {
+                    abstract String second();
+                }
+            }
+            "#).unwrap();
+        let methods = result.class_declarations.iter()
+            .map(|c| (c.name.as_str(), c.methods.iter().map(|m| m.name.as_str()).collect::<Vec<_>>()))
+            .collect::<Vec<_>>();
+        assert_eq!(methods, vec![("Value$Ünïcode", vec!["first"]), ("Nested", vec!["second"])]);
+    }
+
+    #[test]
+    fn syntax_errors_are_reported_with_position() {
+        assert!(super::check_syntax("Valid.java", "class Valid { int x() { return 1; } }").is_ok());
+        let result = super::check_syntax("Broken.java", "class Broken {\n  abstract int x(\n}\n");
+        assert!(matches!(result, Err(super::ParseError::SyntaxError(position)) if position.starts_with("Broken.java:2:")));
+    }
+
+    #[test]
+    fn unsupported_features_are_reported_at_the_member_name() {
+        let source_code = r#"
+            import com.google.auto.value.AutoValue;
+            import com.google.auto.value.extension.memoized.Memoized;
+            @AutoValue
+            abstract class Value {
+                abstract String name();
+                @Memoized String upperName() { return name().toUpperCase(); }
+                @AutoValue.Builder
+                abstract static class Builder {}
+            }
+            "#;
+        let tree = super::parse_tree(source_code, None).unwrap();
+        let (result, diagnostics) = super::collect_parse_result(&tree, source_code).unwrap();
+        assert_eq!(result.class_declarations.len(), 1);
+        let reported = diagnostics.iter()
+            .map(|d| (d.start.row, d.message.split(' ').next().unwrap()))
+            .collect::<Vec<_>>();
+        assert_eq!(reported, vec![(6, "@Memoized"), (8, "@AutoValue.Builder")]);
+        assert_eq!(diagnostics[1].end.column - diagnostics[1].start.column, "Builder".len());
+    }
+
+    #[test]
+    fn files_without_autovalue_classes_keep_their_package() {
+        let source_code = "package com.example;\nclass Plain {}\n";
+        assert!(!super::may_contain_autovalue(source_code));
+        let result = super::parse_source("Plain.java", source_code).unwrap();
+        assert_eq!(result.package_name, "com.example");
+        assert!(result.class_declarations.is_empty());
+    }
+
+    #[test]
+    fn parallel_parsing_matches_sequential_parsing() {
+        use rayon::prelude::*;
+
+        let sources = (0..64)
+            .map(|i| format!(r#"
+                package com.example{i};
+                import com.google.auto.value.AutoValue;
+                @AutoValue
+                abstract class Value{i} {{
+                    abstract int field{i}();
+                }}
+                "#))
+            .collect::<Vec<String>>();
+        let parse = |source: &String| format!("{:?}", super::parse_source("Value.java", source).unwrap());
+
+        let sequential = sources.iter().map(parse).collect::<Vec<String>>();
+        let parallel = sources.par_iter().map(parse).collect::<Vec<String>>();
+        assert_eq!(sequential, parallel);
+        assert!(parallel[63].contains("Value63"));
+    }
+
+    /// Parses the source and checks if its first class declaration is an AutoValue class
+    fn is_autovalue_class(source_code: &str) -> bool {
         let mut parser = super::Parser::new();
         parser.set_language(&tree_sitter_java::language()).unwrap();
+        let tree = parser.parse(source_code, None).unwrap();
+
+        let package_name = super::collect_package(&tree, source_code).unwrap();
+        let imports = super::collect_import_statements(&tree, source_code);
+        let scope = TypeScope::new(
+            &package_name,
+            &imports,
+            super::collect_declared_types(&tree, source_code));
+
+        let root_node = tree.root_node();
+        let mut cursor = root_node.walk();
+        let class_node = root_node.named_children(&mut cursor)
+            .find(|n| n.kind() == "class_declaration")
+            .unwrap();
+        super::has_autovalue_annotation(class_node, source_code, &scope)
+    }
+
+    #[test]
+    fn find_annotation_when_only_one_present() {
+        assert!(is_autovalue_class(r#"
+            import com.google.auto.value.AutoValue;
 
-        let source_code = r#"
             @AutoValue
             public abstract class TestClass {
                 public abstract String getName();
             }
-            "#;
-        let tree = parser.parse(source_code, None).unwrap();
-        assert!(super::has_autovalue_annotation(
-            // The root node is a 'program' node, so the first child is the 'class_declaration'
-            tree.root_node().child(0).unwrap(),
-            source_code,
-            "TestClass"));
+            "#));
     }
 
     #[test]
     fn test_annotation_when_multiple_present() {
-        let mut parser = super::Parser::new();
-        parser.set_language(&tree_sitter_java::language()).unwrap();
+        assert!(is_autovalue_class(r#"
+            import com.google.auto.value.AutoValue;
 
-        let source_code = r#"
             @AutoValue
             @SomeOtherAnnotation
             public abstract class TestClass {
                 public abstract String getName();
             }
-            "#;
-        let tree = parser.parse(source_code, None).unwrap();
-        println!("{}", tree.root_node().child(0).unwrap().to_sexp());
-        assert!(super::has_autovalue_annotation(
-            // The root node is a 'program' node, so the first child is the 'class_declaration'
-            tree.root_node().child(0).unwrap(),
-            source_code,
-            "TestClass"));
+            "#));
+
+        assert!(is_autovalue_class(r#"
+            import com.google.auto.value.AutoValue;
 
-        let source_code = r#"
             @SomeOtherAnnotation
             @AutoValue
             public abstract class TestClass {
                 public abstract String getName();
             }
-            "#;
-        let tree = parser.parse(source_code, None).unwrap();
-        assert!(super::has_autovalue_annotation(
-            // The root node is a 'program' node, so the first child is the 'class_declaration'
-            tree.root_node().child(0).unwrap(),
-            source_code,
-            "TestClass"));
+            "#));
     }
 
     #[test]
     fn test_annotation_when_not_present() {
+        assert!(!is_autovalue_class(r#"
+            import com.google.auto.value.AutoValue;
+
+            public abstract class TestClass {
+                public abstract String getName();
+            }
+            "#));
+    }
+
+    #[test]
+    fn test_annotation_when_not_present_other_annotations_present() {
+        assert!(!is_autovalue_class(r#"
+            import com.google.auto.value.AutoValue;
+
+            @SomeOtherAnnotation
+            public abstract class TestClass {
+                public abstract String getName();
+            }
+            "#));
+    }
+
+    #[test]
+    fn test_annotation_fully_qualified_or_wildcard_imported() {
+        assert!(is_autovalue_class(r#"
+            @com.google.auto.value.AutoValue
+            public abstract class TestClass {
+                public abstract String getName();
+            }
+            "#));
+
+        assert!(is_autovalue_class(r#"
+            import com.google.auto.value.*;
+
+            @AutoValue
+            public abstract class TestClass {
+                public abstract String getName();
+            }
+            "#));
+    }
+
+    #[test]
+    fn test_annotation_with_other_autovalue_annotation() {
+        assert!(!is_autovalue_class(r#"
+            @AutoValue
+            public abstract class TestClass {
+                public abstract String getName();
+            }
+            "#));
+
+        assert!(!is_autovalue_class(r#"
+            import com.example.AutoValue;
+
+            @AutoValue
+            public abstract class TestClass {
+                public abstract String getName();
+            }
+            "#));
+
+        assert!(!is_autovalue_class(r#"
+            import com.google.auto.value.AutoValue;
+
+            @AutoValue
+            public abstract class TestClass {
+                public abstract String getName();
+
+                @interface AutoValue {}
+            }
+            "#));
+    }
+
+    #[test]
+    fn qualified_nullable_annotation_is_recognized() {
         let mut parser = super::Parser::new();
         parser.set_language(&tree_sitter_java::language()).unwrap();
 
         let source_code = r#"
-            public abstract class TestClass {
-                public abstract String getName();
+            @com.google.auto.value.AutoValue
+            abstract class TestClass {
+                @javax.annotation.Nullable abstract String name();
+                abstract String otherName();
             }
             "#;
         let tree = parser.parse(source_code, None).unwrap();
-        assert!(!super::has_autovalue_annotation(
-            // The root node is a 'program' node, so the first child is the 'class_declaration'
-            tree.root_node().child(0).unwrap(),
-            source_code,
-            "TestClass"));
+        let scope = TypeScope::new("", &[], Default::default());
+        let classes = super::collect_classes(&tree, source_code, &scope, &mut vec![]).unwrap();
+        let methods = &classes[0].methods;
+        assert_eq!(methods[0].nullable_annotation().unwrap().name, "javax.annotation.Nullable");
+        assert!(!methods[1].is_nullable());
     }
 
     #[test]
-    fn test_annotation_when_not_present_other_annotations_present() {
+    fn parent_chain_includes_all_type_declarations() {
         let mut parser = super::Parser::new();
         parser.set_language(&tree_sitter_java::language()).unwrap();
 
         let source_code = r#"
-            @SomeOtherAnnotation
-            public abstract class TestClass {
-                public abstract String getName();
+            interface Schema {
+                enum Kind {
+                    VALUE;
+
+                    record Wrapper(String value) {
+                        @interface Marker {
+                            @com.google.auto.value.AutoValue
+                            abstract class Event {
+                                abstract String name();
+                            }
+                        }
+                    }
+                }
             }
             "#;
         let tree = parser.parse(source_code, None).unwrap();
-        assert!(!super::has_autovalue_annotation(
-            // The root node is a 'program' node, so the first child is the 'class_declaration'
-            tree.root_node().child(0).unwrap(),
-            source_code,
-            "TestClass"));
+        let scope = TypeScope::new("", &[], Default::default());
+        let classes = super::collect_classes(&tree, source_code, &scope, &mut vec![]).unwrap();
+        assert_eq!(classes.len(), 1);
+        assert_eq!(classes[0].name, "Event");
+        assert_eq!(classes[0].parent_chain, vec!["Marker", "Wrapper", "Kind", "Schema"]);
+    }
+
+    fn parse_package(source_code: &str) -> String {
+        let mut parser = super::Parser::new();
+        parser.set_language(&tree_sitter_java::language()).unwrap();
+        let tree = parser.parse(source_code, None).unwrap();
+        super::collect_package(&tree, source_code).unwrap()
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn package_with_annotations_comments_and_whitespace() {
+        assert_eq!(parse_package("package com.example;"), "com.example");
+        assert_eq!(parse_package("package   com\n  .example ;"), "com.example");
+        assert_eq!(parse_package("package com /* c */ .example;"), "com.example");
+        assert_eq!(parse_package("@ParametersAreNonnullByDefault\npackage com.example;"), "com.example");
+        assert_eq!(parse_package("package example;"), "example");
+    }
+
+    #[test]
+    fn default_package_has_empty_name() {
+        assert_eq!(parse_package("@AutoValue abstract class TestClass {}"), "");
+    }
+}
@@ -0,0 +1,150 @@
+use std::collections::HashSet;
+
+use crate::parse::ImportDeclaration;
+use crate::util;
+
+/// Qualified names of the annotations that mavir keys off of.
+pub const AUTO_VALUE: &str = "com.google.auto.value.AutoValue";
+pub const AUTO_VALUE_BUILDER: &str = "com.google.auto.value.AutoValue.Builder";
+pub const MEMOIZED: &str = "com.google.auto.value.extension.memoized.Memoized";
+
+const KNOWN_ANNOTATIONS: [&str; 3] = [AUTO_VALUE, AUTO_VALUE_BUILDER, MEMOIZED];
+
+/// The annotation the generated accessors are always marked with.
+pub const OVERRIDE: &str = "java.lang.Override";
+
+/// The names visible to a single source file: its package, its imports and the types it
+/// declares. Used to resolve type names (as written in the source) to qualified names.
+#[derive(Debug)]
+pub struct TypeScope<'a> {
+    package_name: &'a str,
+    imports: &'a [ImportDeclaration],
+    declared_types: HashSet<String>,
+}
+
+impl<'a> TypeScope<'a> {
+    pub fn new(
+        package_name: &'a str,
+        imports: &'a [ImportDeclaration],
+        declared_types: HashSet<String>,
+    ) -> Self {
+        TypeScope { package_name, imports, declared_types }
+    }
+
+    /// Resolves a (possibly qualified) type name to its fully-qualified name. Returns `None`
+    /// when the name can't be determined from the file alone, such as names that may come
+    /// from a wildcard import or from another file in the same package.
+    pub fn resolve(&self, name: &str) -> Option<String> {
+        let (head, rest) = match name.split_once('.') {
+            Some((head, rest)) => (head, Some(rest)),
+            None => (name, None),
+        };
+        let qualify = |prefix: &str| match rest {
+            Some(rest) => format!("{}.{}", prefix, rest),
+            None => prefix.to_string(),
+        };
+
+        // Types declared in this file shadow anything that is imported
+        if self.declared_types.contains(head) {
+            return Some(qualify(&self.qualify_in_package(head)));
+        }
+        let single_import = self.imports.iter()
+            .find(|import| import.simple_name() == Some(head));
+        if let Some(import) = single_import {
+            return Some(qualify(&import.name));
+        }
+        // By convention only package names start with a lower-case letter, so a qualified
+        // name with a lower-case head is already fully-qualified.
+        if rest.is_some() && head.starts_with(|c: char| c.is_lowercase()) {
+            return Some(name.to_string());
+        }
+        if util::is_java_lang_type(head) {
+            return Some(qualify(&format!("java.lang.{}", head)));
+        }
+        None
+    }
+
+    /// Determines if the type name (as written in the source) could refer to the given
+    /// fully-qualified name. Names that don't resolve unambiguously are matched against every
+    /// wildcard import and the file's own package.
+    pub fn refers_to(&self, name: &str, qualified_name: &str) -> bool {
+        if let Some(resolved) = self.resolve(name) {
+            return resolved == qualified_name;
+        }
+        self.qualify_in_package(name) == qualified_name
+            || self.imports.iter()
+                .filter(|import| import.is_wildcard)
+                .any(|import| format!("{}.{}", import.name, name) == qualified_name)
+    }
+
+    /// Resolves an annotation name. Names that can't be resolved unambiguously are resolved to
+    /// the annotation mavir keys off of that they could refer to, if any.
+    pub fn resolve_annotation(&self, name: &str) -> Option<String> {
+        self.resolve(name).or_else(|| {
+            KNOWN_ANNOTATIONS.iter()
+                .find(|known| self.refers_to(name, known))
+                .map(|known| known.to_string())
+        })
+    }
+
+    fn qualify_in_package(&self, name: &str) -> String {
+        if self.package_name.is_empty() {
+            name.to_string()
+        } else {
+            format!("{}.{}", self.package_name, name)
+        }
+    }
+}
+
+/// AutoValue treats any annotation with the simple name `Nullable` as marking a nullable
+/// property, regardless of the package it comes from.
+pub fn is_nullable_annotation(name: &str) -> bool {
+    name.rsplit('.').next() == Some("Nullable")
+}
+
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashSet;
+    use crate::parse::ImportDeclaration;
+    use super::{TypeScope, AUTO_VALUE, AUTO_VALUE_BUILDER};
+
+    fn import(name: &str, is_wildcard: bool) -> ImportDeclaration {
+        ImportDeclaration { name: name.to_string(), is_static: false, is_wildcard }
+    }
+
+    #[test]
+    fn resolves_through_single_type_imports() {
+        let imports = vec![import("com.google.auto.value.AutoValue", false)];
+        let scope = TypeScope::new("com.example", &imports, HashSet::new());
+        assert!(scope.refers_to("AutoValue", AUTO_VALUE));
+        assert!(scope.refers_to("AutoValue.Builder", AUTO_VALUE_BUILDER));
+        assert!(scope.refers_to("com.google.auto.value.AutoValue", AUTO_VALUE));
+    }
+
+    #[test]
+    fn resolves_through_wildcard_imports() {
+        let imports = vec![import("com.google.auto.value", true)];
+        let scope = TypeScope::new("com.example", &imports, HashSet::new());
+        assert_eq!(scope.resolve("AutoValue"), None);
+        assert_eq!(scope.resolve_annotation("AutoValue").as_deref(), Some(AUTO_VALUE));
+        assert!(scope.refers_to("AutoValue", AUTO_VALUE));
+        assert!(scope.refers_to("AutoValue.Builder", AUTO_VALUE_BUILDER));
+    }
+
+    #[test]
+    fn unimported_and_shadowed_names_do_not_match() {
+        let scope = TypeScope::new("com.example", &[], HashSet::new());
+        assert!(!scope.refers_to("AutoValue", AUTO_VALUE));
+
+        let imports = vec![import("com.example.annotations.AutoValue", false)];
+        let scope = TypeScope::new("com.example", &imports, HashSet::new());
+        assert!(!scope.refers_to("AutoValue", AUTO_VALUE));
+
+        let imports = vec![import("com.google.auto.value.AutoValue", false)];
+        let declared = HashSet::from(["AutoValue".to_string()]);
+        let scope = TypeScope::new("com.example", &imports, declared);
+        assert!(!scope.refers_to("AutoValue", AUTO_VALUE));
+        assert!(scope.refers_to("com.google.auto.value.AutoValue", AUTO_VALUE));
+    }
+}
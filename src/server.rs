@@ -0,0 +1,534 @@
+use std::collections::HashMap;
+use std::io::{BufRead, Write};
+
+use log::{debug, error};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use thiserror::Error;
+use tree_sitter::{InputEdit, Point, Tree};
+
+use mavir::generate;
+use mavir::parse::{self, Diagnostic, ParseResult};
+
+#[derive(Debug, Error)]
+pub enum ServerError {
+    #[error("Could not read message: {0}")]
+    CannotReadMessage(String),
+    #[error("Could not write message: {0}")]
+    CannotWriteMessage(String),
+}
+
+pub type Result<T> = std::result::Result<T, ServerError>;
+
+/// JSON-RPC error codes, as used by the Language Server Protocol
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+const INTERNAL_ERROR: i64 = -32603;
+
+/// Diagnostic severities, as used by the Language Server Protocol
+const ERROR: u8 = 1;
+const WARNING: u8 = 2;
+
+/// Serves the generated sources of open documents over JSON-RPC, using the Language Server
+/// Protocol's framing and document synchronization. Documents are parsed again after each
+/// change, reusing the unchanged parts of their previous tree, and diagnostics are published
+/// for syntax errors and for the AutoValue features that mavir doesn't support. Documents that
+/// can't be processed are still kept open, with the error published as a diagnostic. The
+/// generated sources of a document
+/// are returned by the `mavir/generatedSources` request. Serves until the client sends `exit`
+/// or closes the input.
+pub fn serve(mut input: impl BufRead, mut output: impl Write) -> Result<()> {
+    let mut server = Server::default();
+    while let Some(message) = read_message(&mut input)? {
+        let Ok(message) = serde_json::from_slice::<Message>(&message) else {
+            error!("Ignoring malformed message: {}", String::from_utf8_lossy(&message));
+            continue;
+        };
+        debug!("Received {} ({:?})", message.method, message.id);
+        if message.method == "exit" {
+            break;
+        }
+        let result = server.handle(&message.method, message.params, &mut output);
+        // Notifications don't get a response, even when they fail
+        match (message.id, result) {
+            (Some(id), Ok(result)) => {
+                write_message(&mut output, &json!({"jsonrpc": "2.0", "id": id, "result": result}))?;
+            }
+            (Some(id), Err((code, message))) => {
+                let error = json!({"code": code, "message": message});
+                write_message(&mut output, &json!({"jsonrpc": "2.0", "id": id, "error": error}))?;
+            }
+            (None, Err((_, message))) => error!("{}", message),
+            (None, Ok(_)) => {}
+        }
+    }
+    Ok(())
+}
+
+/// A request (with an id) or notification (without one) from the client.
+#[derive(Debug, Deserialize)]
+struct Message {
+    id: Option<Value>,
+    method: String,
+    #[serde(default)]
+    params: Value,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct TextDocumentIdentifier {
+    uri: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct TextDocumentItem {
+    uri: String,
+    text: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct DidOpenParams {
+    text_document: TextDocumentItem,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct DidChangeParams {
+    text_document: TextDocumentIdentifier,
+    content_changes: Vec<ContentChange>,
+}
+
+/// A change to a document, replacing either a range of it or (without a range) all of it.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ContentChange {
+    range: Option<Range>,
+    text: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct DocumentParams {
+    text_document: TextDocumentIdentifier,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+struct Range {
+    start: Position,
+    end: Position,
+}
+
+/// A position in a document as sent by the client, with the character offset counted in
+/// UTF-16 code units.
+#[derive(Debug, Clone, Copy, Deserialize)]
+struct Position {
+    line: usize,
+    character: usize,
+}
+
+type HandlerResult = std::result::Result<Value, (i64, String)>;
+
+#[derive(Default)]
+struct Server {
+    documents: HashMap<String, Document>,
+}
+
+impl Server {
+    /// Handles a message, returning the result for requests. Notifications that change a
+    /// document also publish its diagnostics.
+    fn handle(&mut self, method: &str, params: Value, output: &mut impl Write) -> HandlerResult {
+        match method {
+            "initialize" => Ok(json!({
+                "capabilities": {
+                    // Incremental document synchronization
+                    "textDocumentSync": {"openClose": true, "change": 2},
+                },
+                "serverInfo": {"name": "mavir", "version": env!("CARGO_PKG_VERSION")},
+            })),
+            "initialized" => Ok(Value::Null),
+            "shutdown" => Ok(Value::Null),
+            "textDocument/didOpen" => {
+                let params = parse_params::<DidOpenParams>(params)?;
+                let uri = params.text_document.uri;
+                let document = Document::open(&uri, params.text_document.text);
+                self.documents.insert(uri.clone(), document);
+                self.publish_diagnostics(&uri, output)
+            }
+            "textDocument/didChange" => {
+                let params = parse_params::<DidChangeParams>(params)?;
+                let uri = params.text_document.uri;
+                self.document(&uri)?.change(&uri, &params.content_changes);
+                self.publish_diagnostics(&uri, output)
+            }
+            "textDocument/didClose" => {
+                let params = parse_params::<DocumentParams>(params)?;
+                let uri = params.text_document.uri;
+                self.documents.remove(&uri);
+                publish(output, &uri, vec![])
+            }
+            "mavir/generatedSources" => {
+                let params = parse_params::<DocumentParams>(params)?;
+                let document = self.document(&params.text_document.uri)?;
+                let files = generate::generate_sources(std::slice::from_ref(&document.parse_result));
+                Ok(json!({"files": files}))
+            }
+            _ => Err((METHOD_NOT_FOUND, format!("Unsupported method: {}", method))),
+        }
+    }
+
+    fn document(&mut self, uri: &str) -> std::result::Result<&mut Document, (i64, String)> {
+        self.documents.get_mut(uri)
+            .ok_or_else(|| (INVALID_PARAMS, format!("Document is not open: {}", uri)))
+    }
+
+    fn publish_diagnostics(&mut self, uri: &str, output: &mut impl Write) -> HandlerResult {
+        let document = self.document(uri)?;
+        let to_json = |diagnostic: &Diagnostic, severity: u8| json!({
+            "range": {
+                "start": document.position(diagnostic.start),
+                "end": document.position(diagnostic.end),
+            },
+            "severity": severity,
+            "source": "mavir",
+            "message": diagnostic.message,
+        });
+        let diagnostics = document.errors.iter()
+            .map(|error| to_json(error, ERROR))
+            // Warnings, since the generated code is still usable apart from the feature
+            .chain(document.warnings.iter().map(|warning| to_json(warning, WARNING)))
+            .collect();
+        publish(output, uri, diagnostics)
+    }
+}
+
+fn parse_params<T: serde::de::DeserializeOwned>(params: Value) -> std::result::Result<T, (i64, String)> {
+    serde_json::from_value(params).map_err(|e| (INVALID_PARAMS, e.to_string()))
+}
+
+fn publish(output: &mut impl Write, uri: &str, diagnostics: Vec<Value>) -> HandlerResult {
+    let notification = json!({
+        "jsonrpc": "2.0",
+        "method": "textDocument/publishDiagnostics",
+        "params": {"uri": uri, "diagnostics": diagnostics},
+    });
+    write_message(output, &notification).map_err(|e| (INTERNAL_ERROR, e.to_string()))?;
+    Ok(Value::Null)
+}
+
+/// An open document, along with its latest tree and the results of parsing it. The results
+/// always belong to the current text: when it can't be processed, they are empty apart from
+/// the error.
+struct Document {
+    text: String,
+    /// The tree of the text, unless it couldn't be parsed at all
+    tree: Option<Tree>,
+    parse_result: ParseResult,
+    /// Syntax errors, and errors that prevented processing the text
+    errors: Vec<Diagnostic>,
+    /// The AutoValue features used by the text that mavir doesn't support
+    warnings: Vec<Diagnostic>,
+}
+
+impl Document {
+    fn open(uri: &str, text: String) -> Document {
+        debug!("Opening {}", uri);
+        let mut document = Document {
+            text,
+            tree: None,
+            parse_result: ParseResult::default(),
+            errors: vec![],
+            warnings: vec![],
+        };
+        document.parse(None);
+        document
+    }
+
+    /// Applies the changes in order, then parses the document again. Ranged changes are also
+    /// applied to the old tree, so that only the edited parts of it need to be parsed again.
+    fn change(&mut self, uri: &str, changes: &[ContentChange]) {
+        let mut old_tree = self.tree.take();
+        for change in changes {
+            match change.range {
+                Some(range) => {
+                    let start_byte = self.offset(range.start);
+                    let old_end_byte = self.offset(range.end).max(start_byte);
+                    let start_position = point_at(&self.text, start_byte);
+                    let old_end_position = point_at(&self.text, old_end_byte);
+                    self.text.replace_range(start_byte..old_end_byte, &change.text);
+                    let new_end_byte = start_byte + change.text.len();
+                    if let Some(tree) = old_tree.as_mut() {
+                        tree.edit(&InputEdit {
+                            start_byte,
+                            old_end_byte,
+                            new_end_byte,
+                            start_position,
+                            old_end_position,
+                            new_end_position: point_at(&self.text, new_end_byte),
+                        });
+                    }
+                }
+                None => {
+                    self.text = change.text.clone();
+                    old_tree = None;
+                }
+            }
+        }
+        debug!("Reparsing {} ({})", uri, if old_tree.is_some() { "incrementally" } else { "fully" });
+        self.parse(old_tree.as_ref());
+    }
+
+    /// Parses the text, replacing the results of the previous text. Errors are recorded as
+    /// diagnostics covering the start of the document.
+    fn parse(&mut self, old_tree: Option<&Tree>) {
+        self.parse_result = ParseResult::default();
+        self.errors.clear();
+        self.warnings.clear();
+        let document_error = |e: parse::ParseError| Diagnostic {
+            start: Point::default(),
+            end: Point::default(),
+            message: e.to_string(),
+        };
+
+        self.tree = match parse::parse_tree(&self.text, old_tree) {
+            Ok(tree) => Some(tree),
+            Err(e) => {
+                self.errors.push(document_error(e));
+                return;
+            }
+        };
+        let tree = self.tree.as_ref().unwrap();
+        // The parser recovers from syntax errors, so the rest of the document is still used
+        self.errors.extend(parse::syntax_error(tree));
+        match parse::collect_parse_result(tree, &self.text) {
+            Ok((parse_result, warnings)) => (self.parse_result, self.warnings) = (parse_result, warnings),
+            Err(e) => self.errors.push(document_error(e)),
+        }
+    }
+
+    /// Returns the byte offset of a client position. Positions past the end of a line refer to
+    /// the end of that line, and positions past the last line to the end of the document.
+    fn offset(&self, position: Position) -> usize {
+        let Some(line_start) = line_start(&self.text, position.line) else {
+            return self.text.len();
+        };
+        let line = self.text[line_start..].split('\n').next().unwrap_or_default();
+        let mut utf16_offset = 0;
+        for (byte_offset, c) in line.char_indices() {
+            if utf16_offset >= position.character {
+                return line_start + byte_offset;
+            }
+            utf16_offset += c.len_utf16();
+        }
+        line_start + line.len()
+    }
+
+    /// Converts a tree position (with a byte column) to a client position.
+    fn position(&self, point: Point) -> Value {
+        let line_start = line_start(&self.text, point.row).unwrap_or(self.text.len());
+        let column_end = (line_start + point.column).min(self.text.len());
+        let character = self.text[line_start..column_end].encode_utf16().count();
+        json!({"line": point.row, "character": character})
+    }
+}
+
+/// Returns the byte offset at which the (zero-based) line starts, if the text has that line.
+fn line_start(text: &str, line: usize) -> Option<usize> {
+    if line == 0 {
+        return Some(0);
+    }
+    text.match_indices('\n').nth(line - 1).map(|(index, _)| index + 1)
+}
+
+/// Returns the tree position (with a byte column) of a byte offset.
+fn point_at(text: &str, offset: usize) -> Point {
+    let before = &text[..offset];
+    let row = before.matches('\n').count();
+    let column = before.rfind('\n').map_or(offset, |index| offset - index - 1);
+    Point { row, column }
+}
+
+/// Reads the content of a message framed by a `Content-Length` header. Returns `None` once the
+/// input is closed.
+fn read_message(input: &mut impl BufRead) -> Result<Option<Vec<u8>>> {
+    let read_error = |e: &dyn std::fmt::Display| ServerError::CannotReadMessage(e.to_string());
+
+    let mut content_length = None;
+    loop {
+        let mut header = String::new();
+        if input.read_line(&mut header).map_err(|e| read_error(&e))? == 0 {
+            return match content_length {
+                None => Ok(None),
+                Some(_) => Err(read_error(&"input closed within a message header")),
+            };
+        }
+        let header = header.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.eq_ignore_ascii_case("Content-Length") {
+                content_length = Some(value.trim().parse::<usize>().map_err(|e| read_error(&e))?);
+            }
+        }
+    }
+
+    let content_length = content_length.ok_or_else(|| read_error(&"missing Content-Length header"))?;
+    let mut content = vec![0; content_length];
+    input.read_exact(&mut content).map_err(|e| read_error(&e))?;
+    Ok(Some(content))
+}
+
+fn write_message(output: &mut impl Write, message: &Value) -> Result<()> {
+    let content = message.to_string();
+    write!(output, "Content-Length: {}\r\n\r\n{}", content.len(), content)
+        .and_then(|_| output.flush())
+        .map_err(|e| ServerError::CannotWriteMessage(e.to_string()))
+}
+
+
+#[cfg(test)]
+mod test {
+    use std::io::Cursor;
+    use serde_json::{json, Value};
+    use super::{Document, Position};
+
+    const SOURCE: &str = r#"package com.example;
+import com.google.auto.value.AutoValue;
+import com.google.auto.value.extension.memoized.Memoized;
+@AutoValue
+abstract class Value {
+    abstract String name();
+    @Memoized String upper() { return "é" + name(); }
+}
+"#;
+
+    fn frame(messages: &[Value]) -> Vec<u8> {
+        messages.iter()
+            .flat_map(|m| {
+                let content = m.to_string();
+                format!("Content-Length: {}\r\n\r\n{}", content.len(), content).into_bytes()
+            })
+            .collect()
+    }
+
+    fn unframe(mut output: &[u8]) -> Vec<Value> {
+        let mut messages = vec![];
+        while let Some(message) = super::read_message(&mut output).unwrap() {
+            messages.push(serde_json::from_slice(&message).unwrap());
+        }
+        messages
+    }
+
+    #[test]
+    fn serves_generated_sources_of_edited_documents() {
+        let uri = "file:///src/com/example/Value.java";
+        let input = frame(&[
+            json!({"jsonrpc": "2.0", "id": 1, "method": "initialize", "params": {}}),
+            json!({"jsonrpc": "2.0", "method": "textDocument/didOpen", "params": {
+                "textDocument": {"uri": uri, "languageId": "java", "version": 1, "text": SOURCE},
+            }}),
+            // Renames name() to label() by replacing "name" on line 5
+            json!({"jsonrpc": "2.0", "method": "textDocument/didChange", "params": {
+                "textDocument": {"uri": uri, "version": 2},
+                "contentChanges": [{"range": {
+                    "start": {"line": 5, "character": 20}, "end": {"line": 5, "character": 24},
+                }, "text": "label"}],
+            }}),
+            json!({"jsonrpc": "2.0", "id": 2, "method": "mavir/generatedSources", "params": {
+                "textDocument": {"uri": uri},
+            }}),
+            json!({"jsonrpc": "2.0", "id": 3, "method": "mavir/generatedSources", "params": {
+                "textDocument": {"uri": "file:///Unknown.java"},
+            }}),
+            json!({"jsonrpc": "2.0", "id": 4, "method": "shutdown"}),
+            json!({"jsonrpc": "2.0", "method": "exit"}),
+        ]);
+        let mut output = vec![];
+        super::serve(Cursor::new(input), &mut output).unwrap();
+        let messages = unframe(&output);
+        assert_eq!(messages.len(), 6);
+
+        assert_eq!(messages[0]["result"]["capabilities"]["textDocumentSync"]["change"], 2);
+        for diagnostics in [&messages[1], &messages[2]] {
+            assert_eq!(diagnostics["method"], "textDocument/publishDiagnostics");
+            assert_eq!(diagnostics["params"]["diagnostics"][0]["range"]["start"], json!({"line": 6, "character": 21}));
+        }
+
+        let files = messages[3]["result"]["files"].as_array().unwrap();
+        assert_eq!(files.len(), 1);
+        assert_eq!(files[0]["path"], "com/example/AutoValue_Value.java");
+        let contents = files[0]["contents"].as_str().unwrap();
+        assert!(contents.contains("String label()"));
+        assert!(!contents.contains("name"));
+
+        assert_eq!(messages[4]["error"]["code"], -32602);
+        assert_eq!(messages[5], json!({"jsonrpc": "2.0", "id": 4, "result": null}));
+    }
+
+    #[test]
+    fn incremental_changes_match_a_full_parse() {
+        let mut document = Document::open("Value.java", SOURCE.to_string());
+        let change = |start: (usize, usize), end: (usize, usize), text: &str| super::ContentChange {
+            range: Some(super::Range {
+                start: Position { line: start.0, character: start.1 },
+                end: Position { line: end.0, character: end.1 },
+            }),
+            text: text.to_string(),
+        };
+        // Edits after a multi-byte character (counted as one UTF-16 unit), across lines and
+        // past the end of a line
+        document.change("Value.java", &[
+            change((6, 44), (6, 44), "\"x\" + "),
+            change((5, 4), (6, 4), "abstract int count();\n    "),
+            change((7, 1), (7, 99), " // end"),
+        ]);
+
+        let expected = SOURCE
+            .replace("\"é\" + ", "\"é\" + \"x\" + ")
+            .replace("abstract String name();", "abstract int count();")
+            .replace("\n}\n", "\n} // end\n");
+        assert_eq!(document.text, expected);
+        let full_parse = super::parse::parse_tree(&expected, None).unwrap();
+        assert_eq!(document.tree.unwrap().root_node().to_sexp(), full_parse.root_node().to_sexp());
+        let methods = &document.parse_result.class_declarations[0].methods;
+        assert_eq!(methods.iter().map(|m| m.name.as_str()).collect::<Vec<_>>(), vec!["count"]);
+    }
+
+    #[test]
+    fn documents_with_errors_stay_open() {
+        let uri = "file:///src/com/example/Value.java";
+        let broken = SOURCE.replace("abstract String name();", "abstract String name()");
+        let input = frame(&[
+            json!({"jsonrpc": "2.0", "id": 1, "method": "initialize", "params": {}}),
+            json!({"jsonrpc": "2.0", "method": "textDocument/didOpen", "params": {
+                "textDocument": {"uri": uri, "languageId": "java", "version": 1, "text": broken},
+            }}),
+            json!({"jsonrpc": "2.0", "id": 2, "method": "mavir/generatedSources", "params": {
+                "textDocument": {"uri": uri},
+            }}),
+            json!({"jsonrpc": "2.0", "method": "textDocument/didChange", "params": {
+                "textDocument": {"uri": uri, "version": 2},
+                "contentChanges": [{"text": SOURCE}],
+            }}),
+            json!({"jsonrpc": "2.0", "method": "exit"}),
+        ]);
+        let mut output = vec![];
+        super::serve(Cursor::new(input), &mut output).unwrap();
+        let messages = unframe(&output);
+        assert_eq!(messages.len(), 4);
+
+        let diagnostics = messages[1]["params"]["diagnostics"].as_array().unwrap();
+        assert_eq!(diagnostics[0]["severity"], 1);
+        assert!(diagnostics[0]["message"].as_str().unwrap().starts_with("Syntax error"));
+        assert_eq!(diagnostics[0]["range"]["start"]["line"], 5);
+
+        // The document is open, and generated from what could be parsed
+        let files = messages[2]["result"]["files"].as_array().unwrap();
+        assert_eq!(files[0]["path"], "com/example/AutoValue_Value.java");
+
+        let diagnostics = messages[3]["params"]["diagnostics"].as_array().unwrap();
+        assert!(diagnostics.iter().all(|diagnostic| diagnostic["severity"] == 2));
+    }
+}
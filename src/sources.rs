@@ -0,0 +1,204 @@
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use log::debug;
+use thiserror::Error;
+use walkdir::WalkDir;
+
+#[derive(Debug, Error)]
+pub enum SourceError {
+    #[error("Invalid glob pattern '{0}': {1}")]
+    InvalidGlob(String, String),
+    #[error("Could not scan source directory {0}: {1}")]
+    CannotScanDirectory(String, String),
+    #[error("Could not read source JAR {0}: {1}")]
+    CannotReadArchive(String, String),
+}
+
+/// A Java source file read from a source JAR. The path identifies the entry within the
+/// archive, as `path/to/archive.srcjar!/path/to/Entry.java`.
+#[derive(Debug)]
+pub struct ArchiveSource {
+    pub path: String,
+    pub source_code: String,
+}
+
+pub type Result<T> = std::result::Result<T, SourceError>;
+
+/// Recursively collects the Java source files within the given source directories. Globs are
+/// matched against paths relative to the source directory being scanned. When no include
+/// globs are given, all `.java` files are included. The result is sorted, so it doesn't
+/// depend on the order the filesystem lists directory entries in.
+pub fn collect_source_files(
+    source_dirs: &[String],
+    include: &[String],
+    exclude: &[String],
+) -> Result<Vec<String>> {
+    let include = build_glob_set(include)?;
+    let exclude = build_glob_set(exclude)?;
+
+    let mut source_files = vec![];
+    for source_dir in source_dirs {
+        for entry in WalkDir::new(source_dir).follow_links(true) {
+            let entry = entry
+                .map_err(|e| SourceError::CannotScanDirectory(source_dir.to_string(), e.to_string()))?;
+            let path = entry.path();
+            if !entry.file_type().is_file() || path.extension().is_none_or(|ext| ext != "java") {
+                continue;
+            }
+
+            let relative_path = path.strip_prefix(source_dir).unwrap_or(path);
+            if !is_included(relative_path, &include, &exclude) {
+                debug!("Skipping excluded file: {}", path.display());
+                continue;
+            }
+            source_files.push(path.to_string_lossy().to_string());
+        }
+    }
+
+    source_files.sort();
+    source_files.dedup();
+    Ok(source_files)
+}
+
+/// Reads the Java source files contained in a source JAR, without extracting it. Globs are
+/// matched against the entry names. The result is sorted by entry name.
+pub fn read_archive_sources(
+    archive_path: &str,
+    include: &[String],
+    exclude: &[String],
+) -> Result<Vec<ArchiveSource>> {
+    let include = build_glob_set(include)?;
+    let exclude = build_glob_set(exclude)?;
+    let archive_error = |e: &dyn std::fmt::Display| {
+        SourceError::CannotReadArchive(archive_path.to_string(), e.to_string())
+    };
+
+    let file = File::open(archive_path).map_err(|e| archive_error(&e))?;
+    let mut archive = zip::ZipArchive::new(file).map_err(|e| archive_error(&e))?;
+
+    let mut entry_names = archive.file_names()
+        .filter(|name| name.ends_with(".java"))
+        .filter(|name| is_included(Path::new(name), &include, &exclude))
+        .map(str::to_string)
+        .collect::<Vec<String>>();
+    entry_names.sort();
+
+    let mut sources = vec![];
+    for entry_name in entry_names {
+        let mut entry = archive.by_name(&entry_name).map_err(|e| archive_error(&e))?;
+        if !entry.is_file() {
+            continue;
+        }
+        let path = format!("{}!/{}", archive_path, entry_name);
+        let mut source_code = String::new();
+        entry.read_to_string(&mut source_code)
+            .map_err(|e| SourceError::CannotReadArchive(path.clone(), e.to_string()))?;
+        sources.push(ArchiveSource { path, source_code });
+    }
+    Ok(sources)
+}
+
+fn is_included(relative_path: &Path, include: &Option<GlobSet>, exclude: &Option<GlobSet>) -> bool {
+    let included = include.as_ref().is_none_or(|globs| globs.is_match(relative_path));
+    let excluded = exclude.as_ref().is_some_and(|globs| globs.is_match(relative_path));
+    included && !excluded
+}
+
+/// Compiles the glob patterns into a set. Returns `None` when there are no patterns.
+fn build_glob_set(patterns: &[String]) -> Result<Option<GlobSet>> {
+    if patterns.is_empty() {
+        return Ok(None);
+    }
+    let mut builder = GlobSetBuilder::new();
+    for pattern in patterns {
+        let glob = Glob::new(pattern)
+            .map_err(|e| SourceError::InvalidGlob(pattern.to_string(), e.to_string()))?;
+        builder.add(glob);
+    }
+    let glob_set = builder.build()
+        .map_err(|e| SourceError::InvalidGlob(patterns.join(", "), e.to_string()))?;
+    Ok(Some(glob_set))
+}
+
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+    use tempdir::TempDir;
+
+    /// Creates the (empty) files in a new temporary directory
+    fn source_dir(files: &[&str]) -> TempDir {
+        let dir = TempDir::new("mavir-sources").unwrap();
+        for file in files {
+            let path = dir.path().join(file);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, "").unwrap();
+        }
+        dir
+    }
+
+    fn relative_paths(dir: &TempDir, files: Vec<String>) -> Vec<String> {
+        let prefix = format!("{}/", dir.path().display());
+        files.iter()
+            .map(|f| f.strip_prefix(&prefix).unwrap().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn collects_java_files_in_sorted_order() {
+        let dir = source_dir(&["com/b/B.java", "com/a/Z.java", "com/a/A.java", "com/a/README.md"]);
+        let files = super::collect_source_files(
+            &[dir.path().display().to_string()], &[], &[]).unwrap();
+        assert_eq!(relative_paths(&dir, files), vec!["com/a/A.java", "com/a/Z.java", "com/b/B.java"]);
+    }
+
+    #[test]
+    fn include_and_exclude_globs() {
+        let dir = source_dir(&["com/a/A.java", "com/a/ATest.java", "com/b/B.java", "org/C.java"]);
+        let files = super::collect_source_files(
+            &[dir.path().display().to_string()],
+            &["com/**".to_string()],
+            &["**/*Test.java".to_string()]).unwrap();
+        assert_eq!(relative_paths(&dir, files), vec!["com/a/A.java", "com/b/B.java"]);
+    }
+
+    #[test]
+    fn reads_java_entries_from_archive() {
+        use std::io::Write;
+        use zip::write::SimpleFileOptions;
+
+        let dir = TempDir::new("mavir-sources").unwrap();
+        let archive_path = dir.path().join("gen.srcjar");
+        let mut zip = zip::ZipWriter::new(fs::File::create(&archive_path).unwrap());
+        for (name, contents) in [
+            ("com/b/B.java", "class B {}"),
+            ("com/a/A.java", "class A {}"),
+            ("com/a/ATest.java", "class ATest {}"),
+            ("META-INF/MANIFEST.MF", "Manifest-Version: 1.0"),
+        ] {
+            zip.start_file(name, SimpleFileOptions::default()).unwrap();
+            zip.write_all(contents.as_bytes()).unwrap();
+        }
+        zip.finish().unwrap();
+
+        let archive_path = archive_path.display().to_string();
+        let sources = super::read_archive_sources(
+            &archive_path, &[], &["**/*Test.java".to_string()]).unwrap();
+        let entries = sources.iter()
+            .map(|s| (s.path.as_str(), s.source_code.as_str()))
+            .collect::<Vec<_>>();
+        assert_eq!(entries, vec![
+            (format!("{}!/com/a/A.java", archive_path).as_str(), "class A {}"),
+            (format!("{}!/com/b/B.java", archive_path).as_str(), "class B {}"),
+        ]);
+    }
+
+    #[test]
+    fn invalid_glob_is_an_error() {
+        let result = super::collect_source_files(&[], &["com/[".to_string()], &[]);
+        assert!(matches!(result, Err(super::SourceError::InvalidGlob(_, _))));
+    }
+}
@@ -0,0 +1,508 @@
+use std::collections::HashSet;
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+use tree_sitter::Node;
+
+use crate::modifiers::Annotation;
+use crate::parse::{ParseError, Result};
+use crate::resolve::TypeScope;
+
+/// A Java type, as written in the source, built from a tree-sitter type node. Serialized as
+/// an object whose `kind` is the snake_case name of the variant (see `model` for the shapes).
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum JavaType {
+    Primitive {
+        #[serde(rename = "name")]
+        kind: PrimitiveType,
+        annotations: Vec<Annotation>,
+    },
+    Class(ClassType),
+    /// A single array dimension; multi-dimensional arrays nest, outermost first
+    Array {
+        component: Box<JavaType>,
+        annotations: Vec<Annotation>,
+    },
+    /// A reference to a type parameter of the enclosing class
+    TypeVariable {
+        name: String,
+        annotations: Vec<Annotation>,
+    },
+    /// A wildcard type argument, such as `?` or `? extends Number`
+    Wildcard {
+        bound: Option<WildcardBound>,
+        annotations: Vec<Annotation>,
+    },
+}
+
+/// The primitive types of Java.
+/// A primitive type, serialized as its keyword.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PrimitiveType {
+    Boolean,
+    Byte,
+    Short,
+    Int,
+    Long,
+    Char,
+    Float,
+    Double,
+}
+
+/// A class or interface type, such as `String` or `java.util.Map.Entry<K, V>`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ClassType {
+    /// The package, when the type is written fully-qualified (e.g. `java.util` in
+    /// `java.util.List`)
+    pub package: Option<String>,
+    /// The enclosing types of a nested type, outermost first (e.g. `Map` in `Map.Entry`)
+    pub outer_types: Vec<ClassType>,
+    pub name: String,
+    pub type_arguments: Vec<JavaType>,
+    pub annotations: Vec<Annotation>,
+}
+
+/// The bound of a wildcard type argument, serialized as its `kind` (`extends` or `super`) and
+/// bounding `type`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", content = "type", rename_all = "snake_case")]
+pub enum WildcardBound {
+    Extends(Box<JavaType>),
+    Super(Box<JavaType>),
+}
+
+impl JavaType {
+    /// Builds the type from a node in a `type` position. Simple names that match one of
+    /// `type_parameters` are type variables.
+    pub(crate) fn from_node(
+        node: Node,
+        source_code: &str,
+        scope: &TypeScope,
+        type_parameters: &HashSet<String>,
+    ) -> Result<JavaType> {
+        TypeBuilder { source_code, scope, type_parameters }.build(node)
+    }
+
+    pub fn primitive(&self) -> Option<PrimitiveType> {
+        match self {
+            JavaType::Primitive { kind, .. } => Some(*kind),
+            _ => None,
+        }
+    }
+
+    pub fn is_primitive(&self) -> bool {
+        self.primitive().is_some()
+    }
+
+    pub fn is_array(&self) -> bool {
+        matches!(self, JavaType::Array { .. })
+    }
+
+    /// The type annotations that apply to this type itself (not its components or arguments).
+    pub fn annotations(&self) -> &[Annotation] {
+        match self {
+            JavaType::Primitive { annotations, .. }
+            | JavaType::Array { annotations, .. }
+            | JavaType::TypeVariable { annotations, .. }
+            | JavaType::Wildcard { annotations, .. } => annotations,
+            JavaType::Class(class_type) => &class_type.annotations,
+        }
+    }
+
+    /// Adds annotations written before the type. For arrays these apply to the element type.
+    fn prepend_annotations(&mut self, mut leading: Vec<Annotation>) {
+        let annotations = match self {
+            JavaType::Array { component, .. } => return component.prepend_annotations(leading),
+            JavaType::Primitive { annotations, .. }
+            | JavaType::TypeVariable { annotations, .. }
+            | JavaType::Wildcard { annotations, .. } => annotations,
+            JavaType::Class(class_type) => &mut class_type.annotations,
+        };
+        leading.append(annotations);
+        *annotations = leading;
+    }
+}
+
+impl PrimitiveType {
+    fn from_keyword(keyword: &str) -> Option<PrimitiveType> {
+        let primitive = match keyword {
+            "boolean" => PrimitiveType::Boolean,
+            "byte" => PrimitiveType::Byte,
+            "short" => PrimitiveType::Short,
+            "int" => PrimitiveType::Int,
+            "long" => PrimitiveType::Long,
+            "char" => PrimitiveType::Char,
+            "float" => PrimitiveType::Float,
+            "double" => PrimitiveType::Double,
+            _ => return None,
+        };
+        Some(primitive)
+    }
+
+    pub fn keyword(&self) -> &'static str {
+        match self {
+            PrimitiveType::Boolean => "boolean",
+            PrimitiveType::Byte => "byte",
+            PrimitiveType::Short => "short",
+            PrimitiveType::Int => "int",
+            PrimitiveType::Long => "long",
+            PrimitiveType::Char => "char",
+            PrimitiveType::Float => "float",
+            PrimitiveType::Double => "double",
+        }
+    }
+}
+
+/// A single dotted segment of a class type name, e.g. `@A Map<K, V>`.
+struct Segment {
+    name: String,
+    type_arguments: Vec<JavaType>,
+    annotations: Vec<Annotation>,
+}
+
+struct TypeBuilder<'a> {
+    source_code: &'a str,
+    scope: &'a TypeScope<'a>,
+    type_parameters: &'a HashSet<String>,
+}
+
+impl TypeBuilder<'_> {
+    fn build(&self, node: Node) -> Result<JavaType> {
+        match node.kind() {
+            "integral_type" | "floating_point_type" | "boolean_type" => {
+                let keyword = self.text(node);
+                let kind = PrimitiveType::from_keyword(keyword)
+                    .ok_or_else(|| self.unsupported(node))?;
+                Ok(JavaType::Primitive { kind, annotations: vec![] })
+            }
+            "type_identifier" | "scoped_type_identifier" | "generic_type" => {
+                self.build_class_type(node)
+            }
+            "array_type" => {
+                let element = node.child_by_field_name("element")
+                    .ok_or_else(|| self.unsupported(node))?;
+                let dimensions = node.child_by_field_name("dimensions")
+                    .ok_or_else(|| self.unsupported(node))?;
+
+                // Dimensions are written outermost first, so wrap the element innermost first
+                let mut array_type = self.build(element)?;
+                for annotations in self.dimensions(dimensions).into_iter().rev() {
+                    array_type = JavaType::Array { component: Box::new(array_type), annotations };
+                }
+                Ok(array_type)
+            }
+            "annotated_type" => {
+                let mut annotations = vec![];
+                let mut annotated_type = None;
+                let mut cursor = node.walk();
+                for child in node.named_children(&mut cursor) {
+                    match child.kind() {
+                        "marker_annotation" | "annotation" => annotations.extend(self.annotation(child)),
+                        _ if !child.is_extra() => annotated_type = Some(self.build(child)?),
+                        _ => {}
+                    }
+                }
+                let mut annotated_type = annotated_type.ok_or_else(|| self.unsupported(node))?;
+                annotated_type.prepend_annotations(annotations);
+                Ok(annotated_type)
+            }
+            "wildcard" => {
+                let mut annotations = vec![];
+                let mut is_super = false;
+                let mut bound_type = None;
+                let mut cursor = node.walk();
+                for child in node.named_children(&mut cursor) {
+                    match child.kind() {
+                        "marker_annotation" | "annotation" => annotations.extend(self.annotation(child)),
+                        "super" => is_super = true,
+                        _ if !child.is_extra() => bound_type = Some(Box::new(self.build(child)?)),
+                        _ => {}
+                    }
+                }
+                let bound = bound_type.map(|t| {
+                    if is_super { WildcardBound::Super(t) } else { WildcardBound::Extends(t) }
+                });
+                Ok(JavaType::Wildcard { bound, annotations })
+            }
+            _ => Err(self.unsupported(node)),
+        }
+    }
+
+    fn build_class_type(&self, node: Node) -> Result<JavaType> {
+        let mut segments = self.segments(node)?;
+
+        // A lone name matching a type parameter is a type variable
+        if segments.len() == 1 && segments[0].type_arguments.is_empty()
+            && self.type_parameters.contains(&segments[0].name) {
+            let segment = segments.remove(0);
+            return Ok(JavaType::TypeVariable {
+                name: segment.name,
+                annotations: segment.annotations,
+            });
+        }
+
+        // By convention, leading lower-case segments are the package name
+        let package_len = segments.iter()
+            .take(segments.len() - 1)
+            .take_while(|s| {
+                s.name.starts_with(|c: char| c.is_lowercase())
+                    && s.type_arguments.is_empty()
+                    && s.annotations.is_empty()
+            })
+            .count();
+        let package = segments.drain(..package_len)
+            .map(|s| s.name)
+            .collect::<Vec<String>>();
+
+        let last = segments.pop().ok_or_else(|| self.unsupported(node))?;
+        let outer_types = segments.into_iter()
+            .map(|s| ClassType {
+                package: None,
+                outer_types: vec![],
+                name: s.name,
+                type_arguments: s.type_arguments,
+                annotations: s.annotations,
+            })
+            .collect();
+        Ok(JavaType::Class(ClassType {
+            package: if package.is_empty() { None } else { Some(package.join(".")) },
+            outer_types,
+            name: last.name,
+            type_arguments: last.type_arguments,
+            annotations: last.annotations,
+        }))
+    }
+
+    /// Splits a (possibly qualified and generic) class type name into its dotted segments.
+    fn segments(&self, node: Node) -> Result<Vec<Segment>> {
+        match node.kind() {
+            "type_identifier" => Ok(vec![Segment {
+                name: self.text(node).to_string(),
+                type_arguments: vec![],
+                annotations: vec![],
+            }]),
+            "generic_type" => {
+                let mut segments = vec![];
+                let mut type_arguments = vec![];
+                let mut cursor = node.walk();
+                for child in node.named_children(&mut cursor) {
+                    match child.kind() {
+                        "type_arguments" => {
+                            let mut args_cursor = child.walk();
+                            for arg in child.named_children(&mut args_cursor) {
+                                if !arg.is_extra() {
+                                    type_arguments.push(self.build(arg)?);
+                                }
+                            }
+                        }
+                        _ if !child.is_extra() => segments = self.segments(child)?,
+                        _ => {}
+                    }
+                }
+                let last = segments.last_mut().ok_or_else(|| self.unsupported(node))?;
+                last.type_arguments = type_arguments;
+                Ok(segments)
+            }
+            "scoped_type_identifier" => {
+                let mut segments = vec![];
+                let mut annotations = vec![];
+                let mut cursor = node.walk();
+                let children = node.named_children(&mut cursor)
+                    .filter(|n| !n.is_extra())
+                    .collect::<Vec<Node>>();
+                let (name_node, scope_nodes) = children.split_last()
+                    .ok_or_else(|| self.unsupported(node))?;
+                for child in scope_nodes {
+                    match child.kind() {
+                        "marker_annotation" | "annotation" => annotations.extend(self.annotation(*child)),
+                        _ => segments = self.segments(*child)?,
+                    }
+                }
+                segments.push(Segment {
+                    name: self.text(*name_node).to_string(),
+                    type_arguments: vec![],
+                    annotations,
+                });
+                Ok(segments)
+            }
+            _ => Err(self.unsupported(node)),
+        }
+    }
+
+    /// Returns the annotations of each `[]` in a (dimensions) node, outermost first.
+    fn dimensions(&self, node: Node) -> Vec<Vec<Annotation>> {
+        let mut dimensions = vec![];
+        let mut annotations = vec![];
+        let mut cursor = node.walk();
+        for child in node.children(&mut cursor) {
+            match child.kind() {
+                "marker_annotation" | "annotation" => annotations.extend(self.annotation(child)),
+                "]" => dimensions.push(std::mem::take(&mut annotations)),
+                _ => {}
+            }
+        }
+        dimensions
+    }
+
+    fn annotation(&self, node: Node) -> Option<Annotation> {
+        Annotation::from_node(node, self.source_code, self.scope)
+    }
+
+    fn text(&self, node: Node) -> &str {
+        &self.source_code[node.start_byte()..node.end_byte()]
+    }
+
+    fn unsupported(&self, node: Node) -> ParseError {
+        ParseError::FileProcessingError(format!(
+            "Unsupported type '{}' at line {}", self.text(node), node.start_position().row + 1))
+    }
+}
+
+/// Writes annotations followed by a space, e.g. `@Nullable `.
+fn write_annotations(f: &mut fmt::Formatter<'_>, annotations: &[Annotation]) -> fmt::Result {
+    for annotation in annotations {
+        write!(f, "{} ", annotation)?;
+    }
+    Ok(())
+}
+
+fn write_type_arguments(f: &mut fmt::Formatter<'_>, type_arguments: &[JavaType]) -> fmt::Result {
+    if !type_arguments.is_empty() {
+        let arguments = type_arguments.iter()
+            .map(JavaType::to_string)
+            .collect::<Vec<String>>()
+            .join(", ");
+        write!(f, "<{}>", arguments)?;
+    }
+    Ok(())
+}
+
+impl fmt::Display for JavaType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            JavaType::Primitive { kind, annotations } => {
+                write_annotations(f, annotations)?;
+                write!(f, "{}", kind.keyword())
+            }
+            JavaType::Class(class_type) => write!(f, "{}", class_type),
+            JavaType::Array { .. } => {
+                // Written as the element type followed by each dimension, outermost first
+                let mut dimensions = vec![];
+                let mut element = self;
+                while let JavaType::Array { component, annotations } = element {
+                    dimensions.push(annotations);
+                    element = component;
+                }
+                write!(f, "{}", element)?;
+                for annotations in dimensions {
+                    if !annotations.is_empty() {
+                        write!(f, " ")?;
+                        write_annotations(f, annotations)?;
+                    }
+                    write!(f, "[]")?;
+                }
+                Ok(())
+            }
+            JavaType::TypeVariable { name, annotations } => {
+                write_annotations(f, annotations)?;
+                write!(f, "{}", name)
+            }
+            JavaType::Wildcard { bound, annotations } => {
+                write_annotations(f, annotations)?;
+                write!(f, "?")?;
+                match bound {
+                    Some(WildcardBound::Extends(t)) => write!(f, " extends {}", t),
+                    Some(WildcardBound::Super(t)) => write!(f, " super {}", t),
+                    None => Ok(()),
+                }
+            }
+        }
+    }
+}
+
+impl fmt::Display for ClassType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(package) = &self.package {
+            write!(f, "{}.", package)?;
+        }
+        for outer_type in &self.outer_types {
+            write!(f, "{}.", outer_type)?;
+        }
+        write_annotations(f, &self.annotations)?;
+        write!(f, "{}", self.name)?;
+        write_type_arguments(f, &self.type_arguments)
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashSet;
+    use tree_sitter::Parser;
+    use crate::resolve::TypeScope;
+    use super::{JavaType, PrimitiveType, WildcardBound};
+
+    /// Parses the return type of the first method in a class with type parameter `T`
+    fn parse_type(type_text: &str) -> JavaType {
+        let source_code = format!("abstract class TestClass<T> {{ abstract {} value(); }}", type_text);
+        let mut parser = Parser::new();
+        parser.set_language(&tree_sitter_java::language()).unwrap();
+        let tree = parser.parse(&source_code, None).unwrap();
+
+        let class_node = tree.root_node().named_child(0).unwrap();
+        let method = class_node.child_by_field_name("body").unwrap().named_child(0).unwrap();
+        let type_node = method.child_by_field_name("type").unwrap();
+        let scope = TypeScope::new("", &[], Default::default());
+        let type_parameters = HashSet::from(["T".to_string()]);
+        JavaType::from_node(type_node, &source_code, &scope, &type_parameters).unwrap()
+    }
+
+    #[test]
+    fn primitives_and_boxed_types() {
+        assert_eq!(parse_type("double").primitive(), Some(PrimitiveType::Double));
+        assert_eq!(parse_type("boolean").primitive(), Some(PrimitiveType::Boolean));
+
+        let JavaType::Class(boxed) = parse_type("java.lang.Double") else { panic!() };
+        assert_eq!(boxed.package.as_deref(), Some("java.lang"));
+        assert_eq!(boxed.name, "Double");
+
+        let JavaType::Class(boxed) = parse_type("Double") else { panic!() };
+        assert_eq!(boxed.package, None);
+    }
+
+    #[test]
+    fn nested_generic_types() {
+        let value_type = parse_type("java.util.Map.Entry<T, ? super List<@Nullable String>>");
+        let JavaType::Class(entry) = &value_type else { panic!() };
+        assert_eq!(entry.package.as_deref(), Some("java.util"));
+        assert_eq!(entry.outer_types[0].name, "Map");
+        assert_eq!(entry.name, "Entry");
+        assert!(matches!(entry.type_arguments[0], JavaType::TypeVariable { .. }));
+        let JavaType::Wildcard { bound: Some(WildcardBound::Super(list)), .. } = &entry.type_arguments[1]
+            else { panic!() };
+        let JavaType::Class(list) = list.as_ref() else { panic!() };
+        assert_eq!(list.type_arguments[0].annotations()[0].name, "Nullable");
+        assert_eq!(value_type.to_string(), "java.util.Map.Entry<T, ? super List<@Nullable String>>");
+    }
+
+    #[test]
+    fn arrays_and_type_annotations() {
+        let value_type = parse_type("int @A [] []");
+        let JavaType::Array { component, annotations } = &value_type else { panic!() };
+        assert_eq!(annotations[0].name, "A");
+        assert!(component.is_array());
+        assert_eq!(value_type.to_string(), "int @A [][]");
+
+        assert_eq!(parse_type("java.util.@A Map.Entry<String, ?>").to_string(),
+                   "java.util.@A Map.Entry<String, ?>");
+        assert_eq!(parse_type("Outer<String>.Inner").to_string(), "Outer<String>.Inner");
+    }
+
+    #[test]
+    fn multi_line_types_are_normalized() {
+        assert_eq!(parse_type("Map<\n    String,\n    /* values */ List<Integer>>").to_string(),
+                   "Map<String, List<Integer>>");
+    }
+}
@@ -1,24 +1,21 @@
 use std::collections::HashSet;
 use std::sync::OnceLock;
 
-fn java_primitive_types() -> &'static HashSet<&'static str> {
+/// Commonly used types from `java.lang`, which are always in scope without an import.
+fn java_lang_types() -> &'static HashSet<&'static str> {
     static HASHSET: OnceLock<HashSet<&str>> = OnceLock::new();
     HASHSET.get_or_init(|| {
-        let mut set = HashSet::new();
-        set.insert("boolean");
-        set.insert("byte");
-        set.insert("short");
-        set.insert("int");
-        set.insert("long");
-        set.insert("float");
-        set.insert("double");
-        set.insert("char");
-        set
+        [
+            "Boolean", "Byte", "Character", "CharSequence", "Class", "Comparable", "Deprecated",
+            "Double", "Enum", "Exception", "Float", "Integer", "Iterable", "Long", "Math",
+            "NullPointerException", "Number", "Object", "Override", "Record", "RuntimeException",
+            "Short", "String", "StringBuilder", "SuppressWarnings", "System", "Throwable", "Void",
+        ].into_iter().collect()
     })
 }
 
-pub fn is_primitive_type(identifier: &str) -> bool {
-    return java_primitive_types().contains(identifier);
+pub fn is_java_lang_type(identifier: &str) -> bool {
+    java_lang_types().contains(identifier)
 }
 
 
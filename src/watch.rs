@@ -0,0 +1,210 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+use std::time::Duration;
+
+use log::{debug, error, info, warn};
+use notify::{RecursiveMode, Watcher};
+use rayon::prelude::*;
+use thiserror::Error;
+
+use mavir::generate::{self, GenerateError, GeneratedFile};
+use mavir::parse;
+use mavir::sources::{self, SourceError};
+
+#[derive(Debug, Error)]
+pub enum WatchError {
+    #[error("Could not watch {0}: {1}")]
+    CannotWatch(String, String),
+    #[error(transparent)]
+    SourceError(#[from] SourceError),
+    #[error(transparent)]
+    GenerateError(#[from] GenerateError),
+}
+
+pub type Result<T> = std::result::Result<T, WatchError>;
+
+/// Keeps an output directory up to date with the AutoValue classes in a set of source
+/// directories. Only changed files are parsed again, and a file that fails to parse (such as
+/// one that is mid-edit) keeps the output generated from its last good version.
+#[derive(Debug)]
+pub struct Watch {
+    source_dirs: Vec<String>,
+    include: Vec<String>,
+    exclude: Vec<String>,
+    output_dir: String,
+    /// The files generated from each source file, by source file path
+    outputs: BTreeMap<String, Vec<GeneratedFile>>,
+    /// The source file paths, by canonical path, to match the changed paths reported by the
+    /// watcher. Source files are canonicalized once, when first seen.
+    canonical_paths: HashMap<PathBuf, String>,
+}
+
+impl Watch {
+    pub fn new(source_dirs: &[String], include: &[String], exclude: &[String], output_dir: &str) -> Watch {
+        Watch {
+            source_dirs: source_dirs.to_vec(),
+            include: include.to_vec(),
+            exclude: exclude.to_vec(),
+            output_dir: output_dir.to_string(),
+            outputs: BTreeMap::new(),
+            canonical_paths: HashMap::new(),
+        }
+    }
+
+    /// Watches the source directories until interrupted, regenerating the output after each
+    /// burst of changes. A burst ends once no change has been seen for the `debounce` period.
+    pub fn run(mut self, debounce: Duration) -> Result<()> {
+        let (sender, receiver) = mpsc::channel();
+        let mut watcher = notify::recommended_watcher(sender)
+            .map_err(|e| WatchError::CannotWatch(self.source_dirs.join(", "), e.to_string()))?;
+        for source_dir in &self.source_dirs {
+            watcher.watch(Path::new(source_dir), RecursiveMode::Recursive)
+                .map_err(|e| WatchError::CannotWatch(source_dir.to_string(), e.to_string()))?;
+        }
+
+        self.update_and_log(&HashSet::new());
+        info!("Watching {} for changes", self.source_dirs.join(", "));
+        while let Ok(event) = receiver.recv() {
+            let mut changed_paths = HashSet::new();
+            let mut event = Some(event);
+            while let Some(result) = event {
+                match result {
+                    Ok(event) => changed_paths.extend(event.paths),
+                    Err(e) => error!("Error watching files: {}", e),
+                }
+                event = receiver.recv_timeout(debounce).ok();
+            }
+            debug!("Changed paths: {:?}", changed_paths);
+            self.update_and_log(&changed_paths);
+        }
+        Ok(())
+    }
+
+    fn update_and_log(&mut self, changed_paths: &HashSet<PathBuf>) {
+        match self.update(changed_paths) {
+            Ok(()) => info!("Updated {}", self.output_dir),
+            Err(e) => error!("Could not update {}: {}", self.output_dir, e),
+        }
+    }
+
+    /// Parses the source files that changed (and any that haven't been parsed yet), forgets
+    /// the ones that were removed, and then brings the output directory up to date.
+    pub fn update(&mut self, changed_paths: &HashSet<PathBuf>) -> Result<()> {
+        let source_files = sources::collect_source_files(&self.source_dirs, &self.include, &self.exclude)?;
+        let current_files = source_files.iter().collect::<HashSet<&String>>();
+        self.outputs.retain(|source_file, _| current_files.contains(source_file));
+        self.canonical_paths.retain(|_, source_file| current_files.contains(source_file));
+
+        let mut to_process = source_files.iter()
+            .filter(|source_file| !self.outputs.contains_key(*source_file))
+            .cloned()
+            .collect::<HashSet<String>>();
+        for source_file in &to_process {
+            if let Ok(path) = fs::canonicalize(source_file) {
+                self.canonical_paths.insert(path, source_file.clone());
+            }
+        }
+        // Removed files can't be canonicalized, but they were already forgotten above
+        to_process.extend(changed_paths.iter()
+            .filter_map(|path| fs::canonicalize(path).ok())
+            .filter_map(|path| self.canonical_paths.get(&path).cloned()));
+
+        let processed = to_process.into_par_iter()
+            .map(|source_file| {
+                let result = process(&source_file);
+                (source_file, result)
+            })
+            .collect::<Vec<_>>();
+        for (source_file, result) in processed {
+            match result {
+                Ok(generated_files) => {
+                    self.outputs.insert(source_file, generated_files);
+                }
+                Err(e) => {
+                    error!("Could not process {}: {}; keeping its previous output", source_file, e);
+                    self.outputs.entry(source_file).or_default();
+                }
+            }
+        }
+
+        let generated_files = self.outputs.values().flatten().cloned().collect::<Vec<_>>();
+        generate::write_directory(&generated_files, &self.output_dir, None)?;
+        Ok(())
+    }
+}
+
+/// Generates the code for a single source file. Files with syntax errors are rejected rather
+/// than generating code from a partial parse, even when the partial parse found no classes:
+/// some errors (such as an unterminated string) make the parser drop the class entirely.
+fn process(source_file: &str) -> parse::Result<Vec<GeneratedFile>> {
+    let source_code = parse::read_file(source_file)?;
+    if !parse::may_contain_autovalue(&source_code) {
+        return Ok(vec![]);
+    }
+    let tree = parse::parse_tree(&source_code, None)?;
+    parse::check_tree_syntax(source_file, &tree)?;
+    let (parse_result, diagnostics) = parse::collect_parse_result(&tree, &source_code)?;
+    for diagnostic in diagnostics {
+        warn!("{}:{}: {}", source_file, diagnostic.start.row + 1, diagnostic.message);
+    }
+    Ok(generate::generate_sources(std::slice::from_ref(&parse_result)))
+}
+
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashSet;
+    use std::fs;
+    use std::path::PathBuf;
+    use tempdir::TempDir;
+    use super::Watch;
+
+    const SOURCE: &str = r#"
+        package com.example;
+        import com.google.auto.value.AutoValue;
+        @AutoValue
+        abstract class Value {
+            abstract String name();
+        }
+        "#;
+
+    #[test]
+    fn keeps_last_good_output_and_removes_deleted_sources() {
+        let dir = TempDir::new("mavir-watch").unwrap();
+        let source_dir = dir.path().join("src");
+        let source_file = source_dir.join("com/example/Value.java");
+        let output_file = dir.path().join("gen/com/example/AutoValue_Value.java");
+        fs::create_dir_all(source_file.parent().unwrap()).unwrap();
+        fs::write(&source_file, SOURCE).unwrap();
+
+        let mut watch = Watch::new(
+            &[source_dir.display().to_string()], &[], &[], &dir.path().join("gen").display().to_string());
+        watch.update(&HashSet::new()).unwrap();
+        let generated = fs::read_to_string(&output_file).unwrap();
+        assert!(generated.contains("name()"));
+
+        // A half-written edit keeps the previous output
+        let changed = HashSet::from([PathBuf::from(&source_file)]);
+        fs::write(&source_file, SOURCE.replace("abstract String name();", "abstract String name(")).unwrap();
+        watch.update(&changed).unwrap();
+        assert_eq!(fs::read_to_string(&output_file).unwrap(), generated);
+
+        // Even when the parser drops the class entirely, as for an unterminated string
+        fs::write(&source_file, SOURCE.replace("abstract String name();", "String s = \"unterminated;")).unwrap();
+        assert!(mavir::parse::parse_source("Value.java", &fs::read_to_string(&source_file).unwrap())
+            .unwrap().class_declarations.is_empty());
+        watch.update(&changed).unwrap();
+        assert_eq!(fs::read_to_string(&output_file).unwrap(), generated);
+
+        // Finishing the edit regenerates it
+        fs::write(&source_file, SOURCE.replace("name", "label")).unwrap();
+        watch.update(&changed).unwrap();
+        assert!(fs::read_to_string(&output_file).unwrap().contains("label()"));
+
+        fs::remove_file(&source_file).unwrap();
+        watch.update(&changed).unwrap();
+        assert!(!output_file.exists());
+    }
+}
@@ -0,0 +1,373 @@
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Cursor, Read, Write};
+use std::sync::Mutex;
+use std::thread;
+
+use log::{debug, error};
+use prost::Message;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum WorkerError {
+    #[error("Could not read work request: {0}")]
+    CannotReadRequest(String),
+    #[error("Could not write work response: {0}")]
+    CannotWriteResponse(String),
+}
+
+pub type Result<T> = std::result::Result<T, WorkerError>;
+
+/// A request from Bazel, as defined by Bazel's `worker_protocol.proto`. Only the fields that
+/// mavir uses are decoded.
+#[derive(Clone, PartialEq, Message, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct WorkRequest {
+    #[prost(string, repeated, tag = "1")]
+    pub arguments: Vec<String>,
+    /// Non-zero for the requests of a multiplex worker
+    #[prost(int32, tag = "3")]
+    pub request_id: i32,
+    #[prost(bool, tag = "4")]
+    pub cancel: bool,
+    #[prost(int32, tag = "5")]
+    pub verbosity: i32,
+}
+
+/// A response to Bazel, as defined by Bazel's `worker_protocol.proto`.
+#[derive(Clone, PartialEq, Message, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct WorkResponse {
+    #[prost(int32, tag = "1")]
+    pub exit_code: i32,
+    #[prost(string, tag = "2")]
+    pub output: String,
+    #[prost(int32, tag = "3")]
+    pub request_id: i32,
+    #[prost(bool, tag = "4")]
+    pub was_cancelled: bool,
+}
+
+/// The encoding of requests and responses. Bazel uses length-delimited protobuf messages,
+/// unless the action requires the JSON protocol.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Protocol {
+    Proto,
+    Json,
+}
+
+/// Serves work requests read from `input` until it's closed, writing the responses to
+/// `output`. The protocol is detected from the first request (see `detect_protocol`). The
+/// handler is called with the arguments of each request, and returns the exit code and the
+/// output to report. Multiplex requests (those with a non-zero request id) are handled
+/// concurrently and may be cancelled; other requests are handled one at a time.
+pub fn serve<R, W, H>(input: R, output: W, handler: H) -> Result<()>
+where
+    R: Read,
+    W: Write + Send,
+    H: Fn(&[String]) -> (i32, String) + Sync,
+{
+    let mut input = input;
+    let (protocol, prefix) = detect_protocol(&mut input)?;
+    let mut reader = BufReader::new(Cursor::new(prefix).chain(input));
+    debug!("Serving work requests using the {:?} protocol", protocol);
+
+    let requests: Box<dyn Iterator<Item = Result<WorkRequest>>> = match protocol {
+        Protocol::Json => Box::new(
+            serde_json::Deserializer::from_reader(reader)
+                .into_iter::<WorkRequest>()
+                .map(|request| request.map_err(|e| WorkerError::CannotReadRequest(e.to_string())))),
+        Protocol::Proto => Box::new(std::iter::from_fn(move || read_delimited(&mut reader).transpose())),
+    };
+
+    let responder = Responder { output: Mutex::new(output), protocol, in_flight: Mutex::new(HashMap::new()) };
+    let (responder, handler) = (&responder, &handler);
+    thread::scope(|scope| {
+        for request in requests {
+            let request = request?;
+            debug!("Received work request {}: {:?}", request.request_id, request.arguments);
+            if request.cancel {
+                responder.cancel(request.request_id);
+            } else if request.request_id == 0 {
+                let (exit_code, output) = handler(&request.arguments);
+                responder.respond(WorkResponse { exit_code, output, ..Default::default() })?;
+            } else {
+                responder.start(request.request_id);
+                scope.spawn(move || {
+                    let (exit_code, output) = handler(&request.arguments);
+                    let response = WorkResponse { exit_code, output, request_id: request.request_id, was_cancelled: false };
+                    if let Err(e) = responder.finish(response) {
+                        error!("{}", e);
+                    }
+                });
+            }
+        }
+        Ok(())
+    })
+}
+
+/// Detects the protocol from the start of the input, returning the bytes that were read to do
+/// so. JSON requests start with a '{', but so does a protobuf request that is 123 bytes long
+/// (whose length prefix is 0x7B), so the input is only taken as JSON once a whole JSON value
+/// has been read. Input that can't be JSON, or that already holds a complete protobuf request,
+/// is taken as protobuf.
+fn detect_protocol(input: &mut impl Read) -> Result<(Protocol, Vec<u8>)> {
+    let mut prefix = vec![];
+    loop {
+        if let Some(protocol) = classify_prefix(&prefix) {
+            return Ok((protocol, prefix));
+        }
+        let mut chunk = [0; 4096];
+        let read = input.read(&mut chunk).map_err(|e| WorkerError::CannotReadRequest(e.to_string()))?;
+        if read == 0 {
+            // The input is closed, so whatever was read is all there is
+            let protocol = if prefix.first() == Some(&b'{') { Protocol::Json } else { Protocol::Proto };
+            return Ok((protocol, prefix));
+        }
+        prefix.extend_from_slice(&chunk[..read]);
+    }
+}
+
+/// Returns the protocol of input starting with the prefix, or `None` if more input is needed.
+fn classify_prefix(prefix: &[u8]) -> Option<Protocol> {
+    match prefix.first() {
+        None => return None,
+        Some(b'{') => {}
+        Some(_) => return Some(Protocol::Proto),
+    }
+    match serde_json::Deserializer::from_slice(prefix).into_iter::<serde_json::Value>().next() {
+        Some(Ok(_)) => return Some(Protocol::Json),
+        Some(Err(e)) if !e.is_eof() => return Some(Protocol::Proto),
+        _ => {}
+    }
+    let length = prefix[0] as usize;
+    match prefix.get(1..=length) {
+        Some(message) if WorkRequest::decode(message).is_ok() => Some(Protocol::Proto),
+        _ => None,
+    }
+}
+
+/// Reads a protobuf request prefixed with its varint-encoded length. Returns `None` once the
+/// input is closed.
+fn read_delimited(reader: &mut impl BufRead) -> Result<Option<WorkRequest>> {
+    let read_error = |e: &dyn std::fmt::Display| WorkerError::CannotReadRequest(e.to_string());
+
+    let mut length = 0usize;
+    for shift in (0..64).step_by(7) {
+        let buffer = reader.fill_buf().map_err(|e| read_error(&e))?;
+        let Some(&byte) = buffer.first() else {
+            if shift == 0 {
+                return Ok(None);
+            }
+            return Err(read_error(&"input closed within a message length"));
+        };
+        reader.consume(1);
+        length |= ((byte & 0x7f) as usize) << shift;
+        if byte & 0x80 == 0 {
+            break;
+        }
+    }
+
+    let mut message = vec![0; length];
+    reader.read_exact(&mut message).map_err(|e| read_error(&e))?;
+    WorkRequest::decode(message.as_slice())
+        .map(Some)
+        .map_err(|e| read_error(&e))
+}
+
+/// Writes responses, making sure each multiplex request gets exactly one response even when
+/// it's cancelled while in flight.
+struct Responder<W> {
+    output: Mutex<W>,
+    protocol: Protocol,
+    /// The multiplex requests being handled, and whether each has been cancelled
+    in_flight: Mutex<HashMap<i32, bool>>,
+}
+
+impl<W: Write> Responder<W> {
+    fn start(&self, request_id: i32) {
+        self.in_flight.lock().unwrap().insert(request_id, false);
+    }
+
+    /// Responds to a finished request, discarding its result if it was cancelled in the
+    /// meantime.
+    fn finish(&self, response: WorkResponse) -> Result<()> {
+        match self.in_flight.lock().unwrap().remove(&response.request_id) {
+            Some(false) => self.respond(response),
+            Some(true) => self.respond(WorkResponse {
+                request_id: response.request_id,
+                was_cancelled: true,
+                ..Default::default()
+            }),
+            None => Ok(()),
+        }
+    }
+
+    /// Marks a request as cancelled, unless it already finished. Bazel may reuse a cancelled
+    /// request's outputs as soon as it gets a response, so the response is only sent once the
+    /// work has stopped writing them (in `finish`).
+    fn cancel(&self, request_id: i32) {
+        if let Some(cancelled) = self.in_flight.lock().unwrap().get_mut(&request_id) {
+            *cancelled = true;
+        }
+    }
+
+    fn respond(&self, response: WorkResponse) -> Result<()> {
+        let write_error = |e: &dyn std::fmt::Display| WorkerError::CannotWriteResponse(e.to_string());
+        let bytes = match self.protocol {
+            Protocol::Proto => response.encode_length_delimited_to_vec(),
+            Protocol::Json => {
+                let mut json = serde_json::to_vec(&response).map_err(|e| write_error(&e))?;
+                json.push(b'\n');
+                json
+            }
+        };
+        let mut output = self.output.lock().unwrap();
+        output.write_all(&bytes)
+            .and_then(|_| output.flush())
+            .map_err(|e| write_error(&e))
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::sync::mpsc;
+    use std::thread;
+    use std::time::Duration;
+    use prost::Message;
+    use super::{WorkRequest, WorkResponse};
+
+    fn request(arguments: &[&str], request_id: i32) -> WorkRequest {
+        WorkRequest {
+            arguments: arguments.iter().map(|a| a.to_string()).collect(),
+            request_id,
+            ..Default::default()
+        }
+    }
+
+    /// Echoes the arguments back as the output, failing for a "fail" argument
+    fn echo(arguments: &[String]) -> (i32, String) {
+        let exit_code = if arguments.iter().any(|a| a == "fail") { 1 } else { 0 };
+        (exit_code, arguments.join(" "))
+    }
+
+    #[test]
+    fn serves_protobuf_requests_over_a_pipe() {
+        let (request_reader, mut request_writer) = std::io::pipe().unwrap();
+        let (mut response_reader, response_writer) = std::io::pipe().unwrap();
+        let worker = thread::spawn(move || super::serve(request_reader, response_writer, echo));
+
+        // The fake driver sends each request only after reading the previous response
+        let mut responses = vec![];
+        for arguments in [vec!["-f", "A.java"], vec!["fail"]] {
+            request_writer.write_all(&request(&arguments, 0).encode_length_delimited_to_vec()).unwrap();
+            let mut length = [0u8];
+            response_reader.read_exact(&mut length).unwrap();
+            let mut message = vec![0; length[0] as usize];
+            response_reader.read_exact(&mut message).unwrap();
+            responses.push(WorkResponse::decode(message.as_slice()).unwrap());
+        }
+        drop(request_writer);
+        worker.join().unwrap().unwrap();
+
+        assert_eq!(responses, vec![
+            WorkResponse { exit_code: 0, output: "-f A.java".to_string(), ..Default::default() },
+            WorkResponse { exit_code: 1, output: "fail".to_string(), ..Default::default() },
+        ]);
+    }
+
+    #[test]
+    fn detects_protobuf_requests_starting_with_a_brace() {
+        // A 123 byte request has a length prefix of 0x7B, which is '{'
+        let file_path = "x".repeat(117);
+        let arguments = ["-f", file_path.as_str()];
+        let bytes = request(&arguments, 0).encode_length_delimited_to_vec();
+        assert_eq!(bytes[0], b'{');
+
+        let (request_reader, mut request_writer) = std::io::pipe().unwrap();
+        let (mut response_reader, response_writer) = std::io::pipe().unwrap();
+        let worker = thread::spawn(move || super::serve(request_reader, response_writer, echo));
+        request_writer.write_all(&bytes).unwrap();
+        let mut length = [0u8];
+        response_reader.read_exact(&mut length).unwrap();
+        let mut message = vec![0; length[0] as usize];
+        response_reader.read_exact(&mut message).unwrap();
+        drop(request_writer);
+        worker.join().unwrap().unwrap();
+        assert_eq!(WorkResponse::decode(message.as_slice()).unwrap().output, arguments.join(" "));
+    }
+
+    #[test]
+    fn serves_json_multiplex_requests_with_cancellation() {
+        let (request_reader, mut request_writer) = std::io::pipe().unwrap();
+        let (response_reader, response_writer) = std::io::pipe().unwrap();
+
+        // Request 1 blocks until it's released, so it can be cancelled while in flight
+        let (release, released) = mpsc::channel::<()>();
+        let released = std::sync::Mutex::new(released);
+        let handler = move |arguments: &[String]| {
+            if arguments[0] == "block" {
+                released.lock().unwrap().recv_timeout(Duration::from_secs(10)).unwrap();
+            }
+            echo(arguments)
+        };
+        let worker = thread::spawn(move || super::serve(request_reader, response_writer, handler));
+        let mut responses = BufReader::new(response_reader).lines();
+        let mut send = |json: &str| writeln!(request_writer, "{}", json).unwrap();
+        let mut receive = || serde_json::from_str::<WorkResponse>(&responses.next().unwrap().unwrap()).unwrap();
+
+        send(r#"{"arguments": ["block"], "requestId": 1}"#);
+        send(r#"{"arguments": ["-o", "out.srcjar"], "requestId": 2}"#);
+        let second = receive();
+        assert_eq!((second.request_id, second.output.as_str()), (2, "-o out.srcjar"));
+
+        // The cancelled request is only responded to once it finishes, and only once
+        send(r#"{"requestId": 1, "cancel": true}"#);
+        send(r#"{"arguments": ["-f", "B.java"], "requestId": 3}"#);
+        assert_eq!(receive().request_id, 3);
+        release.send(()).unwrap();
+        let cancelled = receive();
+        assert_eq!((cancelled.request_id, cancelled.was_cancelled, cancelled.output.as_str()), (1, true, ""));
+        drop(request_writer);
+        worker.join().unwrap().unwrap();
+        assert!(responses.next().is_none());
+    }
+
+    #[test]
+    fn cancelled_requests_are_responded_to_after_their_outputs_are_written() {
+        let dir = tempdir::TempDir::new("mavir-worker").unwrap();
+        let output_path = dir.path().join("out.srcjar");
+        let (request_reader, mut request_writer) = std::io::pipe().unwrap();
+        let (response_reader, response_writer) = std::io::pipe().unwrap();
+
+        // Request 1 writes its output only after it has been cancelled and released
+        let (release, released) = mpsc::channel::<()>();
+        let released = std::sync::Mutex::new(released);
+        let handler = move |arguments: &[String]| {
+            if arguments[0] == "write" {
+                released.lock().unwrap().recv_timeout(Duration::from_secs(10)).unwrap();
+                std::fs::write(&arguments[1], "contents").unwrap();
+            }
+            echo(arguments)
+        };
+        let worker = thread::spawn(move || super::serve(request_reader, response_writer, handler));
+        let mut responses = BufReader::new(response_reader).lines();
+        let mut send = |json: String| writeln!(request_writer, "{}", json).unwrap();
+        let mut receive = || serde_json::from_str::<WorkResponse>(&responses.next().unwrap().unwrap()).unwrap();
+
+        send(serde_json::json!({"arguments": ["write", output_path], "requestId": 1}).to_string());
+        send(r#"{"requestId": 1, "cancel": true}"#.to_string());
+        send(r#"{"arguments": ["other"], "requestId": 2}"#.to_string());
+        assert_eq!(receive().request_id, 2);
+        assert!(!output_path.exists());
+
+        release.send(()).unwrap();
+        let cancelled = receive();
+        assert_eq!((cancelled.request_id, cancelled.was_cancelled), (1, true));
+        assert!(output_path.exists());
+        drop(request_writer);
+        worker.join().unwrap().unwrap();
+    }
+}
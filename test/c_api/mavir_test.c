@@ -0,0 +1,55 @@
+// Exercises mavir's C API: generates the code for each Java source file given as an argument,
+// printing the path of each generated file and any diagnostics. Exits with a non-zero status if
+// a file can't be read or produces an error diagnostic.
+
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+
+#include "mavir.h"
+
+static char *read_file(const char *path, size_t *len) {
+  FILE *file = fopen(path, "rb");
+  if (file == NULL) {
+    return NULL;
+  }
+  fseek(file, 0, SEEK_END);
+  long size = ftell(file);
+  fseek(file, 0, SEEK_SET);
+  char *contents = malloc(size > 0 ? (size_t)size : 1);
+  *len = fread(contents, 1, (size_t)size, file);
+  fclose(file);
+  return contents;
+}
+
+int main(int argc, char **argv) {
+  printf("mavir %s\n", mavir_version());
+  int failed = 0;
+  for (int i = 1; i < argc; i++) {
+    size_t len = 0;
+    char *source = read_file(argv[i], &len);
+    if (source == NULL) {
+      fprintf(stderr, "Could not read %s\n", argv[i]);
+      return 1;
+    }
+
+    MavirResult *result = mavir_generate(argv[i], (const uint8_t *)source, len);
+    free(source);
+    for (size_t f = 0; f < result->files_len; f++) {
+      const MavirGeneratedFile *file = &result->files[f];
+      if (strstr(file->contents, "class AutoValue_") == NULL) {
+        fprintf(stderr, "%s: unexpected contents for %s\n", argv[i], file->path);
+        failed = 1;
+      }
+      printf("%s\n", file->path);
+    }
+    for (size_t d = 0; d < result->diagnostics_len; d++) {
+      const MavirDiagnostic *diagnostic = &result->diagnostics[d];
+      fprintf(stderr, "%s:%zu:%zu: %s: %s\n", argv[i], diagnostic->start_line, diagnostic->start_column,
+              diagnostic->severity == MavirError ? "error" : "warning", diagnostic->message);
+      failed |= diagnostic->severity == MavirError;
+    }
+    mavir_result_free(result);
+  }
+  return failed;
+}
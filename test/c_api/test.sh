@@ -0,0 +1,53 @@
+#!/usr/bin/env bash
+
+set -euo pipefail
+
+DIR="$( cd "$( dirname "${BASH_SOURCE[0]}" )" && pwd )"
+ROOT="$( cd "${DIR}/../.." && pwd )"
+
+function info() { local msg=$1;
+  echo -e "\033[0;32m[INFO] $msg\033[0m"
+}
+
+function error() { local msg=$1;
+  echo -e "\033[0;31m[ERROR] $msg\033[0m"
+  exit 1
+}
+
+#--------------------------------------------------------------------------------
+# Build the shared library (which also generates the header) and the test program
+
+info "Building libmavir..."
+cargo build --manifest-path "${ROOT}/Cargo.toml" >/dev/null 2>&1 \
+    || error "Failed to build libmavir"
+
+BUILD_DIR="$(mktemp -d)"
+trap 'rm -rf "${BUILD_DIR}"' EXIT
+
+info "Building the C test program..."
+${CC:-cc} -std=c99 -Wall -Wextra -Werror \
+    -I "${ROOT}/include" \
+    -o "${BUILD_DIR}/mavir_test" "${DIR}/mavir_test.c" \
+    -L "${ROOT}/target/debug" -lmavir -Wl,-rpath,"${ROOT}/target/debug" \
+    || error "Failed to build the C test program"
+
+#--------------------------------------------------------------------------------
+# Generate code for the functional test sources through the C API
+
+info "Generating code through the C API..."
+sources=("${ROOT}"/test/java_autovalue/src/main/java/com/github/johnmurray/mavir/*.java)
+output="$("${BUILD_DIR}/mavir_test" "${sources[@]}")" \
+    || error "The C test program failed"
+
+class_name_list=(
+  "AutoValue_TestClass"
+  "AutoValue_OuterClassWithNestedAutoValue_NestedTestClass"
+  "AutoValue_InterfaceWithNestedAutoValue_NestedInInterface"
+  "AutoValue_InterfaceWithNestedAutoValue_NestedEnum_NestedInEnum"
+  "AutoValue_InterfaceWithNestedAutoValue_NestedRecord_NestedInRecord"
+)
+for class_name in "${class_name_list[@]}"; do
+  grep -qx "com/github/johnmurray/mavir/${class_name}.java" <<< "${output}" \
+    || error "Missing generated file for ${class_name}"
+done
+info "Generated all classes through the C API"
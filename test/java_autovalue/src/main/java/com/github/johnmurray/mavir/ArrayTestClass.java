@@ -0,0 +1,14 @@
+package com.github.johnmurray.mavir;
+
+import com.google.auto.value.AutoValue;
+
+@AutoValue
+public abstract class ArrayTestClass {
+    abstract int[] ints();
+
+    public abstract byte[] bytes();
+
+    public static ArrayTestClass create(int[] ints, byte[] bytes) {
+        return new AutoValue_ArrayTestClass(ints, bytes);
+    }
+}
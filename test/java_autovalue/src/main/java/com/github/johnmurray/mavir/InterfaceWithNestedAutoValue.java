@@ -0,0 +1,45 @@
+package com.github.johnmurray.mavir;
+
+import com.google.auto.value.AutoValue;
+
+/**
+ * Validate that AutoValue classes nested in type declarations other than classes
+ * (interfaces, enums and records) are named and qualified like the annotation
+ * processor names them.
+ */
+interface InterfaceWithNestedAutoValue {
+
+    @AutoValue
+    abstract class NestedInInterface {
+        abstract String name();
+
+        static NestedInInterface create(String name) {
+            return new AutoValue_InterfaceWithNestedAutoValue_NestedInInterface(name);
+        }
+    }
+
+    enum NestedEnum {
+        VALUE;
+
+        @AutoValue
+        abstract static class NestedInEnum {
+            abstract int count();
+
+            static NestedInEnum create(int count) {
+                return new AutoValue_InterfaceWithNestedAutoValue_NestedEnum_NestedInEnum(count);
+            }
+        }
+    }
+
+    record NestedRecord(String value) {
+
+        @AutoValue
+        abstract static class NestedInRecord {
+            abstract long id();
+
+            static NestedInRecord create(long id) {
+                return new AutoValue_InterfaceWithNestedAutoValue_NestedRecord_NestedInRecord(id);
+            }
+        }
+    }
+}
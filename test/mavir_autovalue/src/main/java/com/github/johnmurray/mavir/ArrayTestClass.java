@@ -0,0 +1 @@
+../../../../../../../../java_autovalue/src/main/java/com/github/johnmurray/mavir/ArrayTestClass.java
\ No newline at end of file
@@ -38,6 +38,10 @@ info "Diffing the compiled classes..."
 class_name_list=(
   "AutoValue_TestClass"
   "AutoValue_OuterClassWithNestedAutoValue_NestedTestClass"
+  "AutoValue_InterfaceWithNestedAutoValue_NestedInInterface"
+  "AutoValue_InterfaceWithNestedAutoValue_NestedEnum_NestedInEnum"
+  "AutoValue_InterfaceWithNestedAutoValue_NestedRecord_NestedInRecord"
+  "AutoValue_ArrayTestClass"
 )
 
 unset JAVA_TOOL_OPTIONS